@@ -0,0 +1,829 @@
+use crate::commands::music::autoleave::listeners;
+use crate::commands::music::funts::{MAX_VOLUME, format_duration};
+use crate::commands::music::helpers::parse_timestamp;
+use crate::settings::{GuildSettings, MIN_LEAVE_TIMEOUT_SECS};
+use crate::{Context, Error};
+use anyhow::anyhow;
+use poise::ChoiceParameter;
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, ChannelType, GuildId, RoleId, UserId};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+
+/// Longest prefix a server may pick
+const MAX_PREFIX_LEN: usize = 5;
+/// Largest queue a server may allow
+const MAX_QUEUE_LIMIT: usize = 10_000;
+
+/// Whether `user_id` has the DJ role of the guild or may manage it
+pub(crate) async fn is_dj(
+    ctx: &serenity::Context,
+    settings: &GuildSettings,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+) -> bool {
+    let Ok(member) = guild_id.member(ctx, user_id).await else {
+        return false;
+    };
+    if settings
+        .dj_role
+        .is_some_and(|dj_role| member.roles.contains(&dj_role))
+    {
+        return true;
+    }
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return false;
+    };
+    let Some(channel) = guild.channels.get(&channel_id) else {
+        return false;
+    };
+    let permissions = guild.user_permissions_in(channel, &member);
+    permissions.administrator() || permissions.manage_guild()
+}
+
+/// Whether `user_id` is the only one listening in the bot's voice channel
+async fn alone_with_bot(ctx: &serenity::Context, guild_id: GuildId, user_id: UserId) -> bool {
+    let Some(manager) = songbird::get(ctx).await else {
+        return false;
+    };
+    let Some(handler_lock) = manager.get(guild_id) else {
+        return false;
+    };
+    let Some(channel) = handler_lock.lock().await.current_channel() else {
+        return false;
+    };
+    listeners(ctx, guild_id, ChannelId::new(channel.0.get())) == [user_id]
+}
+
+/// Whether a user who is not blocked may use `command`, a top level command name.
+/// Commands the guild restricted to DJs are open to others only when they listen alone.
+pub(crate) async fn may_use(
+    ctx: &serenity::Context,
+    settings: &GuildSettings,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+    command: &str,
+) -> bool {
+    !settings.dj_commands.contains(command)
+        || is_dj(ctx, settings, guild_id, channel_id, user_id).await
+        || (settings.alone_bypass && alone_with_bot(ctx, guild_id, user_id).await)
+}
+
+/// Mentions of the text or voice channels of the guild that the settings allow,
+/// like `<#1> <#2>`, to tell people where to go instead
+pub(crate) fn allowed_channels(
+    ctx: &serenity::Context,
+    settings: &GuildSettings,
+    guild_id: GuildId,
+    voice: bool,
+) -> String {
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return "none".to_string();
+    };
+    let kinds = if voice {
+        [ChannelType::Voice, ChannelType::Stage]
+    } else {
+        [ChannelType::Text, ChannelType::News]
+    };
+    let mut channels: Vec<_> = guild
+        .channels
+        .values()
+        .filter(|channel| kinds.contains(&channel.kind))
+        .filter(|channel| {
+            if voice {
+                settings.allows_voice_channel(channel.id)
+            } else {
+                settings.allows_text_channel(channel.id)
+            }
+        })
+        .map(|channel| (channel.position, channel.id))
+        .collect();
+    if channels.is_empty() {
+        return "none".to_string();
+    }
+    channels.sort();
+    channels
+        .iter()
+        .map(|(_, channel)| format!("<#{}>", channel))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Why a user may not use a command
+pub(crate) enum Refusal {
+    /// Blocked users get no answer at all
+    Blocked,
+    /// Told to the user
+    Reason(String),
+}
+
+/// The rules every command follows, and every button standing for one: blocked
+/// users are ignored, music commands only work in the allowed text channels and
+/// DJ commands only for DJs. `command` is a top level command name.
+pub(crate) async fn check_user(
+    ctx: &serenity::Context,
+    settings: &GuildSettings,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+    command: &str,
+) -> Result<(), Refusal> {
+    if settings.blocked_users.contains(&user_id) {
+        return Err(Refusal::Blocked);
+    }
+    // `config` works everywhere, so a server can't lock itself out
+    if command != "config" && !settings.allows_text_channel(channel_id) {
+        let channels = allowed_channels(ctx, settings, guild_id, false);
+        return Err(Refusal::Reason(format!(
+            "Music commands don't work in <#{}>, use one of these channels: {}",
+            channel_id, channels
+        )));
+    }
+    if !may_use(ctx, settings, guild_id, channel_id, user_id, command).await {
+        return Err(Refusal::Reason(format!(
+            "Only DJs can use `{}` in this server",
+            command
+        )));
+    }
+    Ok(())
+}
+
+/// Run before every command: blocked users are ignored, commands outside the
+/// allowed text channels and DJ commands fail for everyone else with a reason
+pub(crate) async fn check_permissions(ctx: Context<'_>) -> Result<bool, Error> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(true);
+    };
+    let settings = ctx.data().settings.get(guild_id).await;
+    // Restricting a command restricts its subcommands as well
+    let command = ctx.command().qualified_name.clone();
+    let top_level = command.split(' ').next().unwrap_or_default();
+    match check_user(
+        ctx.serenity_context(),
+        &settings,
+        guild_id,
+        ctx.channel_id(),
+        ctx.author().id,
+        top_level,
+    )
+    .await
+    {
+        Ok(()) => Ok(true),
+        Err(Refusal::Blocked) => Ok(false),
+        Err(Refusal::Reason(reason)) => Err(anyhow!(reason).into()),
+    }
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands(
+        "config_get",
+        "config_set",
+        "config_reset",
+        "config_permissions",
+        "config_dj",
+        "config_restrict",
+        "config_unrestrict",
+        "config_alone",
+        "config_block",
+        "config_unblock"
+    ),
+    subcommand_required,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Change the settings of this server and who may use which commands
+pub async fn config(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "permissions",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Show the DJ role, the DJ only commands and the blocked users
+pub async fn config_permissions(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let settings = ctx.data().settings.get(guild_id).await;
+    let dj_role = match settings.dj_role {
+        Some(role) => format!("<@&{}>", role),
+        None => "none, only admins".to_string(),
+    };
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            "none".to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+    let msg = format!(
+        "**DJ role:** {}\n**DJ only commands:** {}\n**Anyone listening alone may use them:** {}\n**Blocked users:** {}",
+        dj_role,
+        list(
+            settings
+                .dj_commands
+                .iter()
+                .map(|command| format!("`{}`", command))
+                .collect()
+        ),
+        if settings.alone_bypass { "yes" } else { "no" },
+        list(
+            settings
+                .blocked_users
+                .iter()
+                .map(|user| format!("<@{}>", user))
+                .collect()
+        ),
+    );
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "dj",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Set the DJ role, leave it out to let only admins use the DJ commands
+pub async fn config_dj(
+    ctx: Context<'_>,
+    #[description = "Role allowed to use the DJ commands"] role: Option<serenity::Role>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let role_id = role.map(|role| role.id);
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| settings.dj_role = role_id)
+        .await?;
+    let msg = match role_id {
+        Some(role_id) => format!("<@&{}> is the DJ role now", role_id),
+        None => "There is no DJ role now, only admins use the DJ commands".to_string(),
+    };
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+/// Top level name of a registered command, so typos are caught
+fn command_name(ctx: &Context<'_>, name: &str) -> anyhow::Result<String> {
+    let name = name.trim().to_lowercase();
+    ctx.framework()
+        .options()
+        .commands
+        .iter()
+        .find(|command| command.name == name)
+        .map(|command| command.name.clone())
+        .ok_or(anyhow!("There is no command called `{}`", name))
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "restrict",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Let only DJs and admins use a command
+pub async fn config_restrict(
+    ctx: Context<'_>,
+    #[description = "Name of the command"] command: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let command = command_name(&ctx, &command)?;
+    if command == "config" {
+        return Err(anyhow!("`config` is for admins already").into());
+    }
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            settings.dj_commands.insert(command.clone());
+        })
+        .await?;
+    ctx.say(format!("Only DJs can use `{}` now", command))
+        .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "unrestrict",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Let everyone use a command again
+pub async fn config_unrestrict(
+    ctx: Context<'_>,
+    #[description = "Name of the command"] command: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let command = command.trim().to_lowercase();
+    let mut removed = false;
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            removed = settings.dj_commands.remove(&command);
+        })
+        .await?;
+    if !removed {
+        return Err(anyhow!("`{}` is not a DJ command", command).into());
+    }
+    ctx.say(format!("Everyone can use `{}` now", command))
+        .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "alone",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Let anyone listening alone with the bot use the DJ commands
+pub async fn config_alone(
+    ctx: Context<'_>,
+    #[description = "Whether listening alone is enough"] enabled: bool,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| settings.alone_bypass = enabled)
+        .await?;
+    let msg = if enabled {
+        "Anyone listening alone with me can use the DJ commands"
+    } else {
+        "Only DJs and admins can use the DJ commands, even when alone"
+    };
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "block",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Ignore every command from a user
+pub async fn config_block(
+    ctx: Context<'_>,
+    #[description = "User to ignore"] user: serenity::User,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    if user.id == ctx.author().id {
+        return Err(anyhow!("You can't block yourself").into());
+    }
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            settings.blocked_users.insert(user.id);
+        })
+        .await?;
+    ctx.say(format!("I'll ignore <@{}> from now on", user.id))
+        .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "unblock",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Listen to a blocked user again
+pub async fn config_unblock(
+    ctx: Context<'_>,
+    #[description = "User to listen to again"] user: serenity::User,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let mut removed = false;
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            removed = settings.blocked_users.remove(&user.id);
+        })
+        .await?;
+    if !removed {
+        return Err(anyhow!("<@{}> is not blocked", user.id).into());
+    }
+    ctx.say(format!("<@{}> can use commands again", user.id))
+        .await?;
+    Ok(())
+}
+
+/// A server setting that `config get/set/reset` work on
+#[derive(Debug, Clone, Copy, PartialEq, poise::ChoiceParameter)]
+pub enum SettingKey {
+    #[name = "prefix"]
+    Prefix,
+    #[name = "volume"]
+    Volume,
+    #[name = "text_channels"]
+    TextChannels,
+    #[name = "voice_channels"]
+    VoiceChannels,
+    #[name = "denied_text_channels"]
+    DeniedTextChannels,
+    #[name = "denied_voice_channels"]
+    DeniedVoiceChannels,
+    #[name = "max_queue"]
+    MaxQueue,
+    #[name = "max_track_length"]
+    MaxTrackLength,
+    #[name = "announce_channel"]
+    AnnounceChannel,
+    #[name = "dj_role"]
+    DjRole,
+    #[name = "alone_timeout"]
+    AloneTimeout,
+    #[name = "idle_timeout"]
+    IdleTimeout,
+    #[name = "channel_uploads"]
+    ChannelUploads,
+    #[name = "max_enqueue"]
+    MaxEnqueue,
+}
+
+/// Every setting key, in the order the choices are declared
+fn setting_keys() -> impl Iterator<Item = SettingKey> {
+    (0..).map_while(SettingKey::from_index)
+}
+
+/// A change to the settings, checked before anything is saved
+type SettingChange = Box<dyn FnOnce(&mut GuildSettings) + Send>;
+
+/// The channels and roles of a guild, copied out of the cache to check values against
+struct GuildInfo {
+    channels: HashMap<ChannelId, ChannelType>,
+    roles: HashSet<RoleId>,
+}
+
+impl GuildInfo {
+    fn new(ctx: &Context<'_>) -> anyhow::Result<Self> {
+        let guild = ctx.guild().ok_or(anyhow!("unable to find guild"))?;
+        Ok(Self {
+            channels: guild
+                .channels
+                .iter()
+                .map(|(id, channel)| (*id, channel.kind))
+                .collect(),
+            roles: guild.roles.keys().copied().collect(),
+        })
+    }
+
+    fn channel(&self, value: &str, voice: bool) -> anyhow::Result<ChannelId> {
+        let id = value
+            .trim()
+            .trim_start_matches("<#")
+            .trim_end_matches('>')
+            .parse::<u64>()
+            .ok()
+            .filter(|id| *id != 0)
+            .ok_or(anyhow!("`{}` is not a channel", value))?;
+        let channel_id = ChannelId::new(id);
+        let wanted = if voice {
+            [ChannelType::Voice, ChannelType::Stage]
+        } else {
+            [ChannelType::Text, ChannelType::News]
+        };
+        match self.channels.get(&channel_id) {
+            Some(kind) if wanted.contains(kind) => Ok(channel_id),
+            Some(_) if voice => Err(anyhow!("<#{}> is not a voice channel", id)),
+            Some(_) => Err(anyhow!("<#{}> is not a text channel", id)),
+            None => Err(anyhow!("There is no channel {} in this server", value)),
+        }
+    }
+
+    fn role(&self, value: &str) -> anyhow::Result<RoleId> {
+        let id = value
+            .trim()
+            .trim_start_matches("<@&")
+            .trim_end_matches('>')
+            .parse::<u64>()
+            .ok()
+            .filter(|id| *id != 0)
+            .ok_or(anyhow!("`{}` is not a role", value))?;
+        let role_id = RoleId::new(id);
+        if !self.roles.contains(&role_id) {
+            return Err(anyhow!("There is no role {} in this server", value));
+        }
+        Ok(role_id)
+    }
+}
+
+/// Whether `value` asks to clear an optional setting
+fn is_none(value: &str) -> bool {
+    matches!(value.trim().to_lowercase().as_str(), "none" | "off" | "")
+}
+
+fn parse_number<T: std::str::FromStr + PartialOrd + std::fmt::Display>(
+    value: &str,
+    min: T,
+    max: T,
+) -> anyhow::Result<T> {
+    let number = value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| anyhow!("`{}` is not a number", value))?;
+    if number < min || number > max {
+        return Err(anyhow!("The value has to be between {} and {}", min, max));
+    }
+    Ok(number)
+}
+
+fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+    parse_timestamp(value).ok_or(anyhow!(
+        "`{}` is not a duration, try `90`, `1:30` or `1m30s`",
+        value
+    ))
+}
+
+/// Seconds to wait before leaving the voice channel
+fn parse_leave_timeout(value: &str) -> anyhow::Result<u64> {
+    let secs = parse_duration(value)?.as_secs();
+    if secs < MIN_LEAVE_TIMEOUT_SECS {
+        return Err(anyhow!(
+            "I wait at least {} seconds before leaving",
+            MIN_LEAVE_TIMEOUT_SECS
+        ));
+    }
+    Ok(secs)
+}
+
+/// Check `value` for `key`, returning the change to make
+fn parse_setting(key: SettingKey, value: &str, guild: &GuildInfo) -> anyhow::Result<SettingChange> {
+    let change: SettingChange = match key {
+        SettingKey::Prefix => {
+            let prefix = value.trim().to_string();
+            if prefix.is_empty()
+                || prefix.chars().count() > MAX_PREFIX_LEN
+                || prefix.contains(char::is_whitespace)
+            {
+                return Err(anyhow!(
+                    "The prefix has to be 1 to {} characters without spaces",
+                    MAX_PREFIX_LEN
+                ));
+            }
+            Box::new(move |settings| settings.prefix = Some(prefix))
+        }
+        SettingKey::Volume => {
+            let volume = parse_number(value, 0, MAX_VOLUME)?;
+            Box::new(move |settings| settings.volume = volume)
+        }
+        SettingKey::TextChannels
+        | SettingKey::VoiceChannels
+        | SettingKey::DeniedTextChannels
+        | SettingKey::DeniedVoiceChannels => {
+            let voice = matches!(
+                key,
+                SettingKey::VoiceChannels | SettingKey::DeniedVoiceChannels
+            );
+            let channels = if is_none(value) {
+                BTreeSet::new()
+            } else {
+                value
+                    .split([',', ' '])
+                    .filter(|part| !part.is_empty())
+                    .map(|part| guild.channel(part, voice))
+                    .collect::<anyhow::Result<BTreeSet<_>>>()?
+            };
+            match key {
+                SettingKey::VoiceChannels => {
+                    Box::new(move |settings| settings.allowed_voice_channels = channels)
+                }
+                SettingKey::DeniedTextChannels => {
+                    Box::new(move |settings| settings.denied_text_channels = channels)
+                }
+                SettingKey::DeniedVoiceChannels => {
+                    Box::new(move |settings| settings.denied_voice_channels = channels)
+                }
+                _ => Box::new(move |settings| settings.allowed_text_channels = channels),
+            }
+        }
+        SettingKey::MaxQueue => {
+            let max_queue = parse_number(value, 1, MAX_QUEUE_LIMIT)?;
+            Box::new(move |settings| settings.max_queue = max_queue)
+        }
+        SettingKey::MaxTrackLength => {
+            let secs = if is_none(value) {
+                0
+            } else {
+                parse_duration(value)?.as_secs()
+            };
+            Box::new(move |settings| settings.max_track_secs = secs)
+        }
+        SettingKey::AnnounceChannel => {
+            let channel = if is_none(value) {
+                None
+            } else {
+                Some(guild.channel(value, false)?)
+            };
+            Box::new(move |settings| settings.announce_channel = channel)
+        }
+        SettingKey::DjRole => {
+            let role = if is_none(value) {
+                None
+            } else {
+                Some(guild.role(value)?)
+            };
+            Box::new(move |settings| settings.dj_role = role)
+        }
+        SettingKey::AloneTimeout => {
+            let secs = parse_leave_timeout(value)?;
+            Box::new(move |settings| settings.alone_timeout_secs = secs)
+        }
+        SettingKey::IdleTimeout => {
+            let secs = parse_leave_timeout(value)?;
+            Box::new(move |settings| settings.idle_timeout_secs = secs)
+        }
+        SettingKey::ChannelUploads => {
+            let uploads = parse_number(value, 1, 50)?;
+            Box::new(move |settings| settings.channel_uploads = uploads)
+        }
+        SettingKey::MaxEnqueue => {
+            let max_enqueue = parse_number(value, 1, MAX_QUEUE_LIMIT)?;
+            Box::new(move |settings| settings.max_enqueue = max_enqueue)
+        }
+    };
+    Ok(change)
+}
+
+/// Put `key` back to its default
+fn reset_setting(key: SettingKey, settings: &mut GuildSettings) {
+    let default = GuildSettings::default();
+    match key {
+        SettingKey::Prefix => settings.prefix = default.prefix,
+        SettingKey::Volume => settings.volume = default.volume,
+        SettingKey::TextChannels => settings.allowed_text_channels = default.allowed_text_channels,
+        SettingKey::VoiceChannels => {
+            settings.allowed_voice_channels = default.allowed_voice_channels
+        }
+        SettingKey::DeniedTextChannels => {
+            settings.denied_text_channels = default.denied_text_channels
+        }
+        SettingKey::DeniedVoiceChannels => {
+            settings.denied_voice_channels = default.denied_voice_channels
+        }
+        SettingKey::MaxQueue => settings.max_queue = default.max_queue,
+        SettingKey::MaxTrackLength => settings.max_track_secs = default.max_track_secs,
+        SettingKey::AnnounceChannel => settings.announce_channel = default.announce_channel,
+        SettingKey::DjRole => settings.dj_role = default.dj_role,
+        SettingKey::AloneTimeout => settings.alone_timeout_secs = default.alone_timeout_secs,
+        SettingKey::IdleTimeout => settings.idle_timeout_secs = default.idle_timeout_secs,
+        SettingKey::ChannelUploads => settings.channel_uploads = default.channel_uploads,
+        SettingKey::MaxEnqueue => settings.max_enqueue = default.max_enqueue,
+    }
+}
+
+/// Mentions of `channels`, or `empty` when there are none
+fn channel_list(channels: &BTreeSet<ChannelId>, empty: &str) -> String {
+    if channels.is_empty() {
+        return empty.to_string();
+    }
+    channels
+        .iter()
+        .map(|channel| format!("<#{}>", channel))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `key` and its current value, like `volume: 80%`
+fn describe_setting(key: SettingKey, settings: &GuildSettings, default_prefix: &str) -> String {
+    let value = match key {
+        // The global prefix keeps working next to the one set for the server
+        SettingKey::Prefix => match &settings.prefix {
+            Some(prefix) => format!("`{}`, `{}` works as well", prefix, default_prefix),
+            None => format!("`{}`", default_prefix),
+        },
+        SettingKey::Volume => format!("{}%", settings.volume),
+        SettingKey::TextChannels => channel_list(&settings.allowed_text_channels, "all"),
+        SettingKey::VoiceChannels => channel_list(&settings.allowed_voice_channels, "all"),
+        SettingKey::DeniedTextChannels => channel_list(&settings.denied_text_channels, "none"),
+        SettingKey::DeniedVoiceChannels => channel_list(&settings.denied_voice_channels, "none"),
+        SettingKey::MaxQueue => format!("{} songs", settings.max_queue),
+        SettingKey::MaxTrackLength => match settings.max_track_secs {
+            0 => "no limit".to_string(),
+            secs => format_duration(Duration::from_secs(secs)),
+        },
+        SettingKey::AnnounceChannel => match settings.announce_channel {
+            Some(channel) => format!("<#{}>", channel),
+            None => "where the music was asked for".to_string(),
+        },
+        SettingKey::DjRole => match settings.dj_role {
+            Some(role) => format!("<@&{}>", role),
+            None => "none".to_string(),
+        },
+        SettingKey::AloneTimeout => {
+            format_duration(Duration::from_secs(settings.alone_timeout_secs))
+        }
+        SettingKey::IdleTimeout => format_duration(Duration::from_secs(settings.idle_timeout_secs)),
+        SettingKey::ChannelUploads => format!("{} songs", settings.channel_uploads),
+        SettingKey::MaxEnqueue => format!("{} songs", settings.max_enqueue),
+    };
+    format!("**{}:** {}", key.name(), value)
+}
+
+fn default_prefix(ctx: &Context<'_>) -> String {
+    ctx.framework()
+        .options()
+        .prefix_options
+        .prefix
+        .clone()
+        .unwrap_or_default()
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "get",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Show one setting of this server, or all of them
+pub async fn config_get(
+    ctx: Context<'_>,
+    #[description = "Setting to show"] key: Option<SettingKey>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let settings = ctx.data().settings.get(guild_id).await;
+    let default_prefix = default_prefix(&ctx);
+    let msg = match key {
+        Some(key) => describe_setting(key, &settings, &default_prefix),
+        None => setting_keys()
+            .map(|key| describe_setting(key, &settings, &default_prefix))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "set",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Change a setting of this server, `none` clears channels and roles
+pub async fn config_set(
+    ctx: Context<'_>,
+    #[description = "Setting to change"] key: SettingKey,
+    #[description = "New value"]
+    #[rest]
+    value: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let change = {
+        let guild = GuildInfo::new(&ctx)?;
+        parse_setting(key, &value, &guild)?
+    };
+    let settings = ctx.data().settings.update(guild_id, change).await?;
+    ctx.say(describe_setting(key, &settings, &default_prefix(&ctx)))
+        .await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "reset",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Put a setting of this server back to its default, or all of them
+pub async fn config_reset(
+    ctx: Context<'_>,
+    #[description = "Setting to reset, every setting when left out"] key: Option<SettingKey>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let keys = match key {
+        Some(key) => vec![key],
+        None => setting_keys().collect(),
+    };
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            for key in &keys {
+                reset_setting(*key, settings);
+            }
+        })
+        .await?;
+    let msg = match key {
+        Some(key) => format!("`{}` is back to its default", key.name()),
+        None => "Every setting is back to its default".to_string(),
+    };
+    ctx.say(msg).await?;
+    Ok(())
+}
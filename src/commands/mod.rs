@@ -1,4 +1,5 @@
 //pub mod askgpt;
+pub mod config;
 pub mod help;
 pub mod music;
 //pub mod roll;
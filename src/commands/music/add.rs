@@ -1,35 +1,65 @@
-use super::funts::create_now_playing_embed;
-use super::{common::join_n_get_voice_channel_handler, helpers::get_yt_sources};
-use crate::{Context, Error, HttpClient, HttpKey};
-use anyhow::{Context as AnyhowContext, Result, anyhow};
-use poise::serenity_prelude::{ActivityData, EditMessage, Message};
-use poise::{self, CreateReply, serenity_prelude as serenity};
+use super::common::get_guild_session;
+use super::common::join_n_get_voice_channel_handler;
+use super::controls::now_playing_components;
+use super::funts::{create_now_playing_embed, format_duration, volume_to_gain};
+use super::library::cover_attachment;
+use super::picker::{PICK_RESULTS, is_search, pick_search_result};
+use super::prefetch::{prefetch_queue, prefetch_upcoming};
+use super::radio::watch_stream_title;
+use super::session::{GuildSession, LoopMode, QueuedItem};
+use super::sources::{PlaylistOptions, ResolveContext};
+use crate::settings::SettingsStore;
+use crate::{Context, Data, Error, HttpClient, HttpKey};
+use anyhow::{Result, anyhow};
+use poise::serenity_prelude::{ActivityData, CreateMessage, EditMessage};
+use poise::{self, serenity_prelude as serenity};
+use rand::seq::SliceRandom;
 use songbird::Call;
 use songbird::{
     Event, EventContext, EventHandler as VoiceEventHandler, TrackEvent,
-    input::{AuxMetadata, Compose, YoutubeDl},
     tracks::{PlayMode, TrackHandle},
 };
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
 use tracing::{info, warn};
 
 #[poise::command(slash_command, prefix_command, guild_only)]
+#[allow(clippy::too_many_arguments)]
 pub async fn play(
     ctx: Context<'_>,
     #[autocomplete = autocomplete_search]
-    #[description = "Play / queue a song from a YouTube URL"]
+    #[description = "YouTube URL or search, a link to an audio file, or local:<search>"]
     url: String,
+    #[description = "Play the first search result without asking"]
+    #[flag]
+    first: bool,
+    #[description = "Playlist position to start from"] start: Option<usize>,
+    #[description = "Playlist position to stop at"] end: Option<usize>,
+    #[description = "Most songs to take from the playlist"] limit: Option<usize>,
+    #[description = "Queue the playlist backwards"]
+    #[flag]
+    reverse: bool,
+    #[description = "Shuffle the songs before queueing them"]
+    #[flag]
+    shuffle: bool,
 ) -> Result<(), Error> {
-    let _ = ctx.defer().await?;
-    let track_handle: TrackHandle = add_songs(ctx, url, false).await?;
+    ctx.defer().await?;
+    let playlist = PlaylistOptions {
+        start,
+        end,
+        limit,
+        reverse,
+        shuffle,
+    };
+    playlist.validate()?;
+    let track_handle: TrackHandle = add_songs(ctx, url, false, first, playlist).await?;
     ctx.reply("is vibing").await?;
-    if track_handle.get_info().await?.playing == PlayMode::Play {
-    } else {
+    if track_handle.get_info().await?.playing != PlayMode::Play {
         track_handle.play()?;
     }
-    return Ok(());
+    Ok(())
 }
 
 #[poise::command(slash_command, prefix_command, guild_only)]
@@ -39,64 +69,56 @@ pub async fn add_to_queue(
     #[description = "Add to queue a song from a YouTube URL"]
     url: String,
 ) -> Result<(), Error> {
-    //let contxt = Arc::new(ctx.clone());
-    let _ = ctx.defer().await?;
-    let track_handle: TrackHandle = add_songs(ctx, url, true).await?;
+    ctx.defer().await?;
+    let track_handle: TrackHandle =
+        add_songs(ctx, url, true, false, PlaylistOptions::default()).await?;
 
-    if track_handle.get_info().await?.playing == PlayMode::Play {
-    } else {
+    if track_handle.get_info().await?.playing != PlayMode::Play {
         track_handle.play()?;
     }
 
-    return Ok(());
+    Ok(())
 }
 
 #[derive(Clone)]
 struct AudioProgressNotifier {
-    //ctx: Arc<crate::Context<'a>>,
-    msg: Arc<Mutex<Option<Message>>>,
-    cur_song: Arc<Mutex<Option<AuxMetadata>>>,
+    session: Arc<GuildSession>,
     guild_id: serenity::GuildId,
     mgr: Arc<songbird::Songbird>,
     http: Arc<serenity::Http>,
 }
 
 #[serenity::async_trait]
-impl<'a> VoiceEventHandler for AudioProgressNotifier {
+impl VoiceEventHandler for AudioProgressNotifier {
     async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
         //info!("Periodic event triggered");
-        if self.msg.lock().await.is_none() || self.cur_song.lock().await.is_none() {
+        let Some(cur_song) = self.session.cur_song.lock().await.clone() else {
             return Some(Event::Cancel);
-        }
-        if let Some(msg) = self.msg.lock().await.as_mut() {
-            let handler = self
-                .mgr
-                .get(self.guild_id)
-                .ok_or(anyhow::anyhow!("Error getting the handler for the guild"))
-                .unwrap();
-            if let Some(metadata) = self.cur_song.lock().await.as_ref() {
-                let track_state = handler
-                    .lock()
-                    .await
-                    .queue()
-                    .current()
-                    .unwrap()
-                    .get_info()
-                    .await
-                    .unwrap();
-                if track_state.playing == PlayMode::Stop
-                    || track_state.playing == PlayMode::Pause
-                    || track_state.playing == PlayMode::End
-                {
-                    let embed = create_now_playing_embed(metadata, &track_state).await;
-                    let edit_builder = EditMessage::default().embed(embed);
-                    msg.edit(&self.http, edit_builder).await.ok();
-                    return Some(Event::Cancel);
-                }
-                let embed = create_now_playing_embed(metadata, &track_state).await;
-                let edit_builder = EditMessage::default().embed(embed);
+        };
+        let Some(handler) = self.mgr.get(self.guild_id) else {
+            return Some(Event::Cancel);
+        };
+        // The voice connection is let go before the message is locked, other
+        // paths lock the connection first and the message second
+        let Some(cur_track) = handler.lock().await.queue().current() else {
+            return Some(Event::Cancel);
+        };
+        let Ok(track_state) = cur_track.get_info().await else {
+            return Some(Event::Cancel);
+        };
+        let loop_mode = *self.session.loop_mode.lock().await;
+        let embed = create_now_playing_embed(&cur_song, &track_state, loop_mode).await;
+        let edit_builder = EditMessage::default().embed(embed);
+        match self.session.now_playing_msg.lock().await.as_mut() {
+            Some(msg) => {
                 msg.edit(&self.http, edit_builder).await.ok();
             }
+            None => return Some(Event::Cancel),
+        }
+        // Paused tracks stop firing periodic events on their own and pick up
+        // again on resume, so only finished tracks cancel the updates
+        if track_state.playing == PlayMode::Stop || track_state.playing == PlayMode::End {
+            return Some(Event::Cancel);
         }
         None
     }
@@ -104,14 +126,13 @@ impl<'a> VoiceEventHandler for AudioProgressNotifier {
 
 #[derive(Clone)]
 struct SongEndNotifier {
-    //ctx: Arc<crate::Context<'a>>,
-    chan_id: serenity::ChannelId,
     guild_id: serenity::GuildId,
     mgr: Arc<songbird::Songbird>,
     http: Arc<serenity::Http>,
-    next: Arc<Mutex<Vec<YoutubeDl<'static>>>>,
-    msg: Arc<Mutex<Option<Message>>>,
-    cur_song: Arc<Mutex<Option<AuxMetadata>>>,
+    session: Arc<GuildSession>,
+    settings: Arc<SettingsStore>,
+    http_client: HttpClient,
+    generation: usize,
 }
 
 // fn check_msg(result: serenity::Result<serenity::Message>) {
@@ -122,23 +143,39 @@ struct SongEndNotifier {
 
 #[serenity::async_trait]
 impl VoiceEventHandler for SongEndNotifier {
-    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
-        let next_song = self.next.lock().await.pop();
-        let queue_len = self.next.lock().await.len();
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if self.session.current_generation() != self.generation {
+            // The track was stopped to make room for a new queue
+            return None;
+        }
+        let errored = matches!(ctx, EventContext::Track(tracks)
+            if tracks.iter().any(|(state, _)| matches!(state.playing, PlayMode::Errored(_))));
+        let looping = *self.session.loop_mode.lock().await == LoopMode::Queue;
+        // `skipto` takes the current song out when it already put it back itself
+        let finished = self.session.cur_song.lock().await.clone();
+        if looping
+            && !errored
+            && let Some(finished) = finished
+        {
+            self.session.queue.lock().await.push_back(finished);
+        }
+        let next_song = self.session.queue.lock().await.pop_front();
+        let queue_len = self.session.queue.lock().await.len();
         info!("Event Queue length is {:?}", queue_len);
-        if let Some(mut next_song) = next_song {
-            info!("Adding Next song, Next song is {:?}", &next_song);
-            if let Ok(metadata) = next_song
-                .aux_metadata()
-                .await
-                .context("Error getting metadata from the input")
-            {
-                self.add_track(&metadata, next_song).await;
-            } else {
-                info!("Error getting metadata from the input");
-                self.act(_ctx).await;
-            };
+        prefetch_upcoming(
+            self.session.clone(),
+            self.http.clone(),
+            self.http_client.clone(),
+        );
+        if let Some(next_song) = next_song {
+            info!(
+                "Adding Next song, Next song is {:?}",
+                &next_song.metadata.title
+            );
+            self.add_track(next_song).await;
         } else {
+            // The queue ran out, nothing is playing any more
+            self.session.cur_song.lock().await.take();
             //     self.cha
             //         .say(&self.http, "No more songs found, ending the queue")
             //         .await
@@ -148,221 +185,351 @@ impl VoiceEventHandler for SongEndNotifier {
         None
     }
 }
-impl<'a> SongEndNotifier {
-    async fn add_track(&self, metadata: &AuxMetadata, input: YoutubeDl<'static>) {
-        let handler = self
-            .mgr
-            .get(self.guild_id)
-            .ok_or(anyhow::anyhow!("Error getting the handler for the guild"))
-            .unwrap();
-        let track_handle = handler.lock().await.enqueue_input(input.into()).await;
-        let track_state = handler
-            .lock()
-            .await
-            .queue()
-            .current()
-            .unwrap()
-            .get_info()
-            .await
-            .unwrap();
-        let embed = create_now_playing_embed(&metadata, &track_state).await;
-        let edit_builder = EditMessage::default().embed(embed);
-        //let edit_builder = EditMessage::new().embed(embed);
-        let mut now_playing_msg = self.msg.lock().await;
-        now_playing_msg
-            .as_mut()
-            .unwrap()
-            .edit(&self.http, edit_builder)
-            .await
-            .ok();
-        self.cur_song.lock().await.replace(metadata.clone());
+impl SongEndNotifier {
+    async fn add_track(&self, item: QueuedItem) {
+        let Some(handler) = self.mgr.get(self.guild_id) else {
+            warn!("Error getting the handler for the guild {}", self.guild_id);
+            return;
+        };
+        let volume = self.settings.get(self.guild_id).await.volume;
+        let loop_mode = *self.session.loop_mode.lock().await;
+        let track_handle = enqueue_item(&mut *handler.lock().await, &item, volume, loop_mode).await;
+        if let Ok(track_state) = track_handle.get_info().await {
+            let embed = create_now_playing_embed(&item, &track_state, loop_mode).await;
+            let edit_builder = with_cover(
+                EditMessage::default()
+                    .embed(embed)
+                    .components(now_playing_components()),
+                &item,
+            )
+            .await;
+            if let Some(msg) = self.session.now_playing_msg.lock().await.as_mut() {
+                msg.edit(&self.http, edit_builder).await.ok();
+            }
+        }
+        self.session.set_current(item.clone()).await;
+        watch_stream_title(self.session.clone(), &item);
+        self.register_track_events(&track_handle);
+    }
 
+    /// Attach the song end and progress notifiers to a freshly enqueued track
+    fn register_track_events(&self, track_handle: &TrackHandle) {
+        // A track that fails to play fires the end event as well, so one event covers both
         let _ = track_handle
-            .add_event(
-                Event::Track(TrackEvent::End),
-                // self.clone()
-                SongEndNotifier {
-                    chan_id: self.chan_id,
-                    guild_id: self.guild_id,
-                    mgr: self.mgr.clone(),
-                    //ctx: Arc::clone(&self.ctx),
-                    http: self.http.clone(),
-                    next: self.next.clone(),
-                    msg: self.msg.clone(),
-                    cur_song: self.cur_song.clone(),
-                },
-            )
+            .add_event(Event::Track(TrackEvent::End), self.clone())
             .map_err(|err| warn!("Error adding track end event: {:?}", err));
         let _ = track_handle
             .add_event(
                 Event::Periodic(Duration::from_secs(1), None),
                 AudioProgressNotifier {
+                    session: self.session.clone(),
                     guild_id: self.guild_id,
                     mgr: self.mgr.clone(),
-                    //ctx:ctx,
                     http: self.http.clone(),
-                    msg: self.msg.clone(),
-                    cur_song: self.cur_song.clone(),
                 },
             )
             .map_err(|err| warn!("Error adding periodic event: {:?}", err));
     }
 }
 
-async fn get_http_client(ctx: &serenity::Context) -> HttpClient {
+/// Hand a queued song over to songbird at the guild volume, starting it from
+/// its linked timestamp if it has one
+async fn enqueue_item(
+    handler: &mut Call,
+    item: &QueuedItem,
+    volume: u16,
+    loop_mode: LoopMode,
+) -> TrackHandle {
+    let track_handle = handler.enqueue_input(item.input.clone().into()).await;
+    let _ = track_handle
+        .set_volume(volume_to_gain(volume))
+        .map_err(|err| warn!("Error setting track volume: {:?}", err));
+    if loop_mode == LoopMode::Track {
+        let _ = track_handle
+            .enable_loop()
+            .map_err(|err| warn!("Error looping track: {:?}", err));
+    }
+    if let Some(start) = item.start {
+        // The seek is carried out once the track is ready, no need to wait for it
+        let _ = track_handle.seek(start);
+    }
+    track_handle
+}
+
+/// Upload the embedded cover of a local song with the now playing message,
+/// or drop the cover of the previous song
+async fn with_cover(builder: EditMessage, item: &QueuedItem) -> EditMessage {
+    match cover_attachment(item).await {
+        Some(cover) => builder.new_attachment(cover),
+        None => builder.remove_all_attachments(),
+    }
+}
+
+pub(crate) async fn get_http_client(ctx: &serenity::Context) -> HttpClient {
     let data = ctx.data.read().await;
     data.get::<HttpKey>()
         .cloned()
         .expect("Guaranteed to exist in the typemap.")
 }
 
-async fn autocomplete_search<'a>(
+/// Limit on the length of autocomplete names and values set by Discord
+const AUTOCOMPLETE_MAX_LEN: usize = 100;
+
+async fn autocomplete_search(
     ctx: Context<'_>,
-    partial: &'a str,
-) -> impl Iterator<Item = String> {
-    if partial.len() < 3 {
-        return vec!["music".to_string()].into_iter();
+    partial: &str,
+) -> impl Iterator<Item = serenity::AutocompleteChoice> {
+    let resolve_ctx = resolve_context(&ctx).await;
+    let mut suggestions: Vec<serenity::AutocompleteChoice> = ctx
+        .data()
+        .resolvers
+        .search(partial, &resolve_ctx, 5)
+        .await
+        .into_iter()
+        // Picking a suggestion plays exactly that song, not a new search for its title
+        .filter_map(|item| {
+            let value = item.metadata.source_url?;
+            if value.chars().count() > AUTOCOMPLETE_MAX_LEN {
+                return None;
+            }
+            let name: String = item
+                .metadata
+                .title
+                .unwrap_or(value.clone())
+                .chars()
+                .take(AUTOCOMPLETE_MAX_LEN)
+                .collect();
+            Some(serenity::AutocompleteChoice::new(name, value))
+        })
+        .collect();
+    if suggestions.is_empty() {
+        suggestions.push("music".into());
     }
-    let http_client = get_http_client(ctx.serenity_context()).await;
-    let sources = YoutubeDl::new_search(http_client, partial)
-        .search(Some(5))
-        .await;
-    match sources {
-        Ok(suggestions) => suggestions
-            .into_iter()
-            .map(|x| x.title.unwrap_or("No suggestion".to_owned()))
-            .collect::<Vec<String>>()
-            .into_iter(),
+    suggestions.into_iter()
+}
 
-        Err(err) => {
-            tracing::info!("Error getting search suggestions: {:?}", err);
-            let default = vec!["music".to_string()];
-            default.into_iter()
-        }
+/// Requester and guild settings handed to the source resolvers
+pub(crate) async fn resolve_context(ctx: &Context<'_>) -> ResolveContext {
+    let settings = match ctx.guild_id() {
+        Some(guild_id) => ctx.data().settings.get(guild_id).await,
+        None => Default::default(),
+    };
+    ResolveContext {
+        http_client: get_http_client(ctx.serenity_context()).await,
+        requester: ctx.author().id,
+        settings,
+        playlist: PlaylistOptions::default(),
     }
-    //let mut sources = get_yt_sources(http_client, url).await.unwrap();
 }
 
 async fn add_songs(
     ctx: Context<'_>,
     url: String,
     add_to_queue: bool,
+    first: bool,
+    playlist: PlaylistOptions,
 ) -> anyhow::Result<TrackHandle> {
-    //    let handler_lock: Arc<Mutex<Call>> =
     let handler_lock: Arc<Mutex<Call>> = join_n_get_voice_channel_handler(&ctx).await?;
 
-    let http_client = get_http_client(ctx.serenity_context()).await;
+    let mut resolve_ctx = resolve_context(&ctx).await;
+    resolve_ctx.playlist = playlist;
+    let resolvers = &ctx.data().resolvers;
+    let results = if is_search(&url) && !first {
+        resolvers.search(&url, &resolve_ctx, PICK_RESULTS).await
+    } else {
+        Vec::new()
+    };
+    let mut sources: VecDeque<QueuedItem> = if results.is_empty() {
+        resolvers.resolve(&url, &resolve_ctx).await?.into()
+    } else {
+        VecDeque::from([pick_search_result(&ctx, results).await?])
+    };
+    if playlist.reverse {
+        sources.make_contiguous().reverse();
+    }
+    if playlist.shuffle {
+        sources.make_contiguous().shuffle(&mut rand::rng());
+    }
+    // Sources that cannot select items themselves are cut down here
+    let max_enqueue = resolve_ctx.settings.max_enqueue.max(1);
+    if sources.len() > max_enqueue {
+        sources.truncate(max_enqueue);
+        ctx.say(format!(
+            "Only the first {} songs were queued, the most this server allows at once",
+            max_enqueue
+        ))
+        .await?;
+    }
+    play_sources(ctx, handler_lock, sources, add_to_queue).await
+}
 
-    let mut sources = get_yt_sources(http_client, url).await?;
-    sources.reverse();
+/// Play resolved songs in the voice channel joined through `handler_lock`,
+/// replacing what is playing or queueing them behind it
+pub(crate) async fn play_sources(
+    ctx: Context<'_>,
+    handler_lock: Arc<Mutex<Call>>,
+    mut sources: VecDeque<QueuedItem>,
+    add_to_queue: bool,
+) -> anyhow::Result<TrackHandle> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let settings = ctx.data().settings.get(guild_id).await;
+    // Live streams have no length to check, neither do some playlist entries
+    if settings.max_track_secs > 0 {
+        let max_length = Duration::from_secs(settings.max_track_secs);
+        let found = sources.len();
+        sources.retain(|item| {
+            item.live
+                || item
+                    .metadata
+                    .duration
+                    .is_none_or(|duration| duration <= max_length)
+        });
+        if sources.is_empty() {
+            return Err(anyhow!(
+                "Songs can be at most {} long in this server",
+                format_duration(max_length)
+            ));
+        }
+        if sources.len() < found {
+            ctx.say(format!(
+                "Left out {} songs longer than {}",
+                found - sources.len(),
+                format_duration(max_length)
+            ))
+            .await?;
+        }
+    }
 
+    let session = get_guild_session(&ctx).await?;
     let mut handler = handler_lock.lock().await;
 
+    // The song that starts right away takes no place in the queue
+    let room = if add_to_queue {
+        settings
+            .max_queue
+            .saturating_sub(session.queue.lock().await.len())
+            + usize::from(handler.queue().current().is_none())
+    } else {
+        settings.max_queue + 1
+    };
+    if room == 0 {
+        return Err(anyhow!(
+            "The queue is full, it holds {} songs in this server",
+            settings.max_queue
+        ));
+    }
+    if sources.len() > room {
+        ctx.say(format!(
+            "The queue is full, {} songs were left out",
+            sources.len() - room
+        ))
+        .await?;
+        sources.truncate(room);
+    }
+
     if !add_to_queue {
+        session.next_generation();
         handler.queue().stop();
+        session.queue.lock().await.clear();
+    } else if let Some(cur_track) = handler.queue().current() {
+        // Something is already playing, the new songs wait behind the pending ones
+        session.queue.lock().await.append(&mut sources);
+        prefetch_queue(ctx.serenity_context(), session).await;
+        return Ok(cur_track);
     }
 
-    let chan_id = ctx.channel_id();
-    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
     //add first song to the queue
-    let metadata: AuxMetadata;
-    //if let Some(mut track_url) = sources.pop() {
-    let mut track_url;
-    loop {
-        track_url = sources.pop().ok_or(anyhow!("track url not found"))?;
-        let metadata_res = track_url
-            .aux_metadata()
-            .await
-            .context("Error getting metadata from the input");
-        if metadata_res.is_err() {
-            let err = metadata_res.as_ref().err().unwrap();
-            warn!("Error getting metadata from the input: {:?} skipping", err);
-            continue;
-        } else {
-            metadata = metadata_res?;
-            break;
-        }
-    }
+    let item = sources.pop_front().ok_or(anyhow!("track url not found"))?;
+    session.queue.lock().await.append(&mut sources);
+    start_playing(
+        ctx.serenity_context(),
+        ctx.data(),
+        guild_id,
+        &mut handler,
+        session,
+        item,
+    )
+    .await
+}
+
+/// Start playing `item` in a guild where songbird has nothing queued, posting the
+/// now playing message in the announce channel, or else the session text
+/// channel, if there is none yet
+pub(crate) async fn start_playing(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: serenity::GuildId,
+    handler: &mut Call,
+    session: Arc<GuildSession>,
+    item: QueuedItem,
+) -> anyhow::Result<TrackHandle> {
+    let metadata = item.metadata.clone();
     info!("Playing song: {:?}", &metadata.title);
 
-    let playing_track_handle = handler.enqueue_input(track_url.clone().into()).await;
-    let mgr = songbird::get(ctx.serenity_context())
-        .await
-        .ok_or(anyhow::anyhow!(
-            "Songbird Voice client placed in at initialisation."
-        ))
-        .unwrap();
-    //let local_queue=Arc::new(Mutex::new(sources)) ;
-    let data = ctx.data();
+    let settings = data.settings.get(guild_id).await;
+    let volume = settings.volume;
+    let loop_mode = *session.loop_mode.lock().await;
+    let playing_track_handle = enqueue_item(handler, &item, volume, loop_mode).await;
+    let mgr = songbird::get(ctx).await.ok_or(anyhow::anyhow!(
+        "Songbird Voice client placed in at initialisation."
+    ))?;
     {
-        let mut queue = data.queue.lock().await;
-        if !add_to_queue {
-            queue.clear();
-        }
-        queue.append(&mut sources);
-        let mut now_playing_msg = data.now_playing_msg.lock().await;
+        let mut now_playing_msg = session.now_playing_msg.lock().await;
 
         if now_playing_msg.is_none() {
-            ctx.serenity_context().set_presence(
-                Some(ActivityData::playing(metadata.title.as_ref().unwrap())),
+            ctx.set_presence(
+                Some(ActivityData::playing(
+                    metadata.title.as_deref().unwrap_or("Unknown Title"),
+                )),
                 serenity::OnlineStatus::Online,
             );
             let msg_string = format!(
                 "Now playing: {} - {}",
-                metadata.title.as_ref().unwrap(),
-                metadata.artist.as_ref().unwrap()
+                metadata.title.as_deref().unwrap_or("Unknown Title"),
+                metadata.artist.as_deref().unwrap_or("Unknown Artist")
             );
-            let poise_builder = CreateReply::default().content(msg_string).ephemeral(false);
-
-            let poise_reply_msg = poise::send_reply(ctx, poise_builder).await?;
-            let poise_msg = poise_reply_msg.into_message().await?;
+            let text_channel = settings
+                .announce_channel
+                .or(*session.text_channel.lock().await)
+                .ok_or(anyhow!(
+                    "No text channel to post the now playing message in"
+                ))?;
+            let msg = text_channel
+                .send_message(ctx, CreateMessage::new().content(msg_string))
+                .await?;
 
-            now_playing_msg.replace(poise_msg);
+            now_playing_msg.replace(msg);
         }
-        let now_playing_embed = create_now_playing_embed(
-            &metadata,
-            &handler.queue().current().unwrap().get_info().await.unwrap(),
+        let now_playing_embed =
+            create_now_playing_embed(&item, &playing_track_handle.get_info().await?, loop_mode)
+                .await;
+        let now_playing_builder = with_cover(
+            EditMessage::default()
+                .embed(now_playing_embed)
+                .components(now_playing_components()),
+            &item,
         )
         .await;
-        let now_playing_builder = EditMessage::default().embed(now_playing_embed);
 
-        now_playing_msg
-            .as_mut()
-            .unwrap()
-            .edit(ctx, now_playing_builder)
-            .await
-            .ok();
-        let cur_song = Arc::new(Mutex::new(Some(metadata)));
+        if let Some(msg) = now_playing_msg.as_mut() {
+            msg.edit(ctx, now_playing_builder).await.ok();
+        }
+    }
+    session.set_current(item.clone()).await;
+    watch_stream_title(session.clone(), &item);
+    let http_client = get_http_client(ctx).await;
+    prefetch_upcoming(session.clone(), ctx.http.clone(), http_client.clone());
 
-        let _ = playing_track_handle.add_event(
-            Event::Track(TrackEvent::End),
-            SongEndNotifier {
-                //ctx: ctx.clone(),
-                chan_id,
-                guild_id,
-                mgr: mgr.clone(),
-                http: ctx.serenity_context().http.clone(),
-                next: data.queue.clone(),
-                msg: data.now_playing_msg.clone(),
-                cur_song: cur_song.clone(),
-            },
-        )?;
-        let _ = playing_track_handle.add_event(
-            Event::Periodic(Duration::from_secs(1), None),
-            AudioProgressNotifier {
-                //ctx: ctx.clone(),
-                http: ctx.serenity_context().http.clone(),
-                msg: data.now_playing_msg.clone(),
-                cur_song,
-                guild_id,
-                mgr,
-            },
-        )?;
+    SongEndNotifier {
+        guild_id,
+        mgr,
+        http: ctx.http.clone(),
+        settings: data.settings.clone(),
+        http_client,
+        generation: session.current_generation(),
+        session,
     }
+    .register_track_events(&playing_track_handle);
 
-    let track_handle = handler
-        .queue()
-        .current()
-        .ok_or(anyhow!("Error getting currently playing track").into());
-    return track_handle;
+    Ok(playing_track_handle)
 }
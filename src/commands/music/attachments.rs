@@ -0,0 +1,68 @@
+use super::add::{get_http_client, play_sources};
+use super::common::join_n_get_voice_channel_handler;
+use super::direct::{AUDIO_EXTENSIONS, file_extension, get_http_source};
+use super::session::QueuedItem;
+use crate::{Context, Error};
+use anyhow::anyhow;
+use poise::serenity_prelude as serenity;
+use songbird::tracks::PlayMode;
+use std::collections::VecDeque;
+
+/// Whether Discord or the file name says the attachment is audio we can decode
+fn is_audio_attachment(attachment: &serenity::Attachment) -> bool {
+    attachment
+        .content_type
+        .as_deref()
+        .is_some_and(|content_type| content_type.starts_with("audio/"))
+        || file_extension(&attachment.filename)
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Join the author's voice channel and play the audio files among `attachments`
+async fn play_attachments(
+    ctx: Context<'_>,
+    attachments: &[serenity::Attachment],
+) -> Result<(), Error> {
+    let audio: Vec<&serenity::Attachment> = attachments
+        .iter()
+        .filter(|attachment| is_audio_attachment(attachment))
+        .collect();
+    if audio.is_empty() {
+        return Err(anyhow!("There is no audio file attached to that message").into());
+    }
+    let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
+    let http_client = get_http_client(ctx.serenity_context()).await;
+    let mut sources: VecDeque<QueuedItem> = VecDeque::new();
+    for attachment in audio {
+        let mut item =
+            get_http_source(http_client.clone(), attachment.url.clone(), ctx.author().id).await?;
+        // Without a title tag the upload's file name reads better than the CDN link
+        if item.metadata.track.is_none() {
+            item.metadata.title = Some(attachment.filename.clone());
+        }
+        sources.push_back(item);
+    }
+    let track_handle = play_sources(ctx, handler_lock, sources, false).await?;
+    ctx.reply("is vibing").await?;
+    if track_handle.get_info().await?.playing != PlayMode::Play {
+        track_handle.play()?;
+    }
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, guild_only, rename = "playfile")]
+/// Play an uploaded audio file (mp3, m4a, flac, ogg or wav)
+pub async fn play_file(
+    ctx: Context<'_>,
+    #[description = "Audio file to play"] file: serenity::Attachment,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    play_attachments(ctx, &[file]).await
+}
+
+#[poise::command(context_menu_command = "Play this attachment", guild_only)]
+/// Play the audio files attached to a message
+pub async fn play_attachment(ctx: Context<'_>, msg: serenity::Message) -> Result<(), Error> {
+    ctx.defer().await?;
+    play_attachments(ctx, &msg.attachments).await
+}
@@ -0,0 +1,229 @@
+use super::funts::{format_duration, leave_voice};
+use super::session::{GuildSession, Sessions};
+use crate::settings::{MIN_LEAVE_TIMEOUT_SECS, SettingsStore};
+use crate::{Context, Data, Error};
+use anyhow::anyhow;
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GuildId, UserId, VoiceState};
+use songbird::tracks::PlayMode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How often the idle watcher looks for guilds where nothing is playing
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Leave the voice channel when alone or idle, turn it off for 24/7 mode
+pub async fn autoleave(
+    ctx: Context<'_>,
+    #[description = "Leave automatically, off keeps the bot connected 24/7"] enabled: Option<bool>,
+    #[description = "Seconds to wait after everyone else left"] alone: Option<u64>,
+    #[description = "Minutes without music before leaving"] idle: Option<u64>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let idle = idle.map(|idle| idle.saturating_mul(60));
+    if alone.is_some_and(|alone| alone < MIN_LEAVE_TIMEOUT_SECS)
+        || idle.is_some_and(|idle| idle < MIN_LEAVE_TIMEOUT_SECS)
+    {
+        return Err(anyhow!(
+            "I wait at least {} seconds before leaving",
+            MIN_LEAVE_TIMEOUT_SECS
+        )
+        .into());
+    }
+    let settings = ctx
+        .data()
+        .settings
+        .update(guild_id, |settings| {
+            if let Some(enabled) = enabled {
+                settings.stay_connected = !enabled;
+            }
+            if let Some(alone) = alone {
+                settings.alone_timeout_secs = alone;
+            }
+            if let Some(idle) = idle {
+                settings.idle_timeout_secs = idle;
+            }
+        })
+        .await?;
+    let msg = if settings.stay_connected {
+        "24/7 mode is on, I stay in the voice channel until told to leave".to_string()
+    } else {
+        format!(
+            "I leave {} after everyone else left, or after {} without music",
+            format_duration(Duration::from_secs(settings.alone_timeout_secs)),
+            format_duration(Duration::from_secs(settings.idle_timeout_secs))
+        )
+    };
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+/// Number of people other than bots in a voice channel
+fn listener_count(ctx: &serenity::Context, guild_id: GuildId, channel_id: ChannelId) -> usize {
+    listeners(ctx, guild_id, channel_id).len()
+}
+
+/// People other than bots in a voice channel
+pub(crate) fn listeners(
+    ctx: &serenity::Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Vec<UserId> {
+    let bot_id = ctx.cache.current_user().id;
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return Vec::new();
+    };
+    guild
+        .voice_states
+        .values()
+        .filter(|state| state.channel_id == Some(channel_id) && state.user_id != bot_id)
+        .filter(|state| {
+            let is_bot = match &state.member {
+                Some(member) => member.user.bot,
+                None => ctx.cache.user(state.user_id).is_some_and(|user| user.bot),
+            };
+            !is_bot
+        })
+        .map(|state| state.user_id)
+        .collect()
+}
+
+/// Voice channel the bot is connected to in a guild
+async fn bot_channel(ctx: &serenity::Context, guild_id: GuildId) -> Option<ChannelId> {
+    let manager = songbird::get(ctx).await?;
+    let handler_lock = manager.get(guild_id)?;
+    let channel = handler_lock.lock().await.current_channel()?;
+    Some(ChannelId::new(channel.0.get()))
+}
+
+/// Leave the voice channel and tell the text channel why
+async fn auto_leave(
+    ctx: &serenity::Context,
+    session: &GuildSession,
+    guild_id: GuildId,
+    reason: &str,
+) -> anyhow::Result<()> {
+    let manager = songbird::get(ctx).await.ok_or(anyhow!(
+        "Songbird Voice client placed in at initialisation."
+    ))?;
+    leave_voice(&manager, session, guild_id, &ctx.http, Some(reason)).await?;
+    info!("Left voice channel in guild {}: {}", guild_id, reason);
+    if let Some(text_channel) = *session.text_channel.lock().await {
+        text_channel.say(&ctx.http, reason).await?;
+    }
+    Ok(())
+}
+
+/// Start the leave timer when the bot is left alone in its voice channel,
+/// and cancel it once someone comes back
+pub(crate) async fn on_voice_state_update(
+    ctx: &serenity::Context,
+    data: &Data,
+    new: &VoiceState,
+) -> anyhow::Result<()> {
+    let Some(guild_id) = new.guild_id else {
+        return Ok(());
+    };
+    let Some(channel_id) = bot_channel(ctx, guild_id).await else {
+        return Ok(());
+    };
+    let session = data.sessions.get(guild_id).await;
+    let mut alone_timer = session.alone_timer.lock().await;
+    if listener_count(ctx, guild_id, channel_id) > 0 {
+        if let Some(timer) = alone_timer.take() {
+            timer.abort();
+        }
+        return Ok(());
+    }
+    let settings = data.settings.get(guild_id).await;
+    if settings.stay_connected
+        || alone_timer
+            .as_ref()
+            .is_some_and(|timer| !timer.is_finished())
+    {
+        return Ok(());
+    }
+
+    let ctx = ctx.clone();
+    let timer_session = session.clone();
+    let grace_period = Duration::from_secs(settings.alone_timeout_secs);
+    alone_timer.replace(tokio::spawn(async move {
+        tokio::time::sleep(grace_period).await;
+        // Forget this timer first, leaving aborts any timer still stored
+        timer_session.alone_timer.lock().await.take();
+        let still_alone = match bot_channel(&ctx, guild_id).await {
+            Some(channel_id) => listener_count(&ctx, guild_id, channel_id) == 0,
+            None => false,
+        };
+        if still_alone {
+            let reason = "Everyone left the voice channel, so I left too";
+            if let Err(err) = auto_leave(&ctx, &timer_session, guild_id, reason).await {
+                warn!("Error leaving empty voice channel: {:?}", err);
+            }
+        }
+    }));
+    Ok(())
+}
+
+/// Leave the voice channel of every guild where nothing has played for too long
+pub(crate) async fn watch_idle(
+    ctx: serenity::Context,
+    sessions: Arc<Sessions>,
+    settings: Arc<SettingsStore>,
+) {
+    let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(manager) = songbird::get(&ctx).await else {
+            continue;
+        };
+        for (guild_id, session) in sessions.all().await {
+            let Some(handler_lock) = manager.get(guild_id) else {
+                continue;
+            };
+            let cur_track = {
+                let handler = handler_lock.lock().await;
+                if handler.current_channel().is_none() {
+                    continue;
+                }
+                handler.queue().current()
+            };
+            let playing = match cur_track {
+                Some(track) => track
+                    .get_info()
+                    .await
+                    .is_ok_and(|state| state.playing == PlayMode::Play),
+                None => false,
+            };
+            if playing {
+                session.mark_active().await;
+                continue;
+            }
+            let guild_settings = settings.get(guild_id).await;
+            let idle_timeout = Duration::from_secs(guild_settings.idle_timeout_secs);
+            let last_active = *session
+                .last_active
+                .lock()
+                .await
+                .get_or_insert(Instant::now());
+            if guild_settings.stay_connected || last_active.elapsed() < idle_timeout {
+                continue;
+            }
+            let reason = format!(
+                "Nothing played for {}, so I left the voice channel",
+                format_duration(idle_timeout)
+            );
+            if let Err(err) = auto_leave(&ctx, &session, guild_id, &reason).await {
+                warn!("Error leaving idle voice channel: {:?}", err);
+            }
+        }
+    }
+}
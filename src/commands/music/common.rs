@@ -1,7 +1,16 @@
+use super::session::GuildSession;
 use crate::Context;
+use crate::commands::config::allowed_channels;
 use songbird::Call;
 use std::sync::Arc;
 
+pub async fn get_guild_session(ctx: &Context<'_>) -> anyhow::Result<Arc<GuildSession>> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| anyhow::anyhow!("Guild ID not found"))?;
+    Ok(ctx.data().sessions.get(guild_id).await)
+}
+
 pub async fn join_n_get_voice_channel_handler(
     ctx: &Context<'_>,
 ) -> anyhow::Result<Arc<tokio::sync::Mutex<Call>>, anyhow::Error> {
@@ -15,17 +24,23 @@ pub async fn join_n_get_voice_channel_handler(
         .ok_or_else(|| anyhow::anyhow!("Guild ID not found"))?;
     let channel_id = ctx
         .guild()
-        .expect("None value when getting guild")
+        .ok_or(anyhow::anyhow!("Guild not found"))?
         .voice_states
         .get(&ctx.author().id)
-        .expect("Errro getting voice states based on author id")
-        .channel_id
+        .and_then(|voice_state| voice_state.channel_id)
         .ok_or(anyhow::anyhow!(
-            "No channel Id found for voice channel 
-    are you connected to voice channel or the guild has one?
-        "
+            "Join a voice channel before invoking this command"
         ))?;
-    let joined_voice_channel = match manager.join(guild_id, channel_id.clone()).await {
+    let settings = ctx.data().settings.get(guild_id).await;
+    if !settings.allows_voice_channel(channel_id) {
+        let channels = allowed_channels(ctx.serenity_context(), &settings, guild_id, true);
+        return Err(anyhow::anyhow!(
+            "I'm not allowed to join <#{}>, I can join: {}",
+            channel_id,
+            channels
+        ));
+    }
+    let joined_voice_channel = match manager.join(guild_id, channel_id).await {
         Ok(res) => res,
         Err(err) => {
             ctx.say("Join a voice channel before invocking this command")
@@ -33,6 +48,9 @@ pub async fn join_n_get_voice_channel_handler(
             return Err(err.into());
         }
     };
+    let session = get_guild_session(ctx).await?;
+    session.text_channel.lock().await.replace(ctx.channel_id());
+    session.mark_active().await;
     Ok(joined_voice_channel)
 }
 
@@ -0,0 +1,199 @@
+use super::funts::{
+    change_volume, create_now_playing_embed, set_loop_mode, shuffle_queue, stop_playback,
+    toggle_pause,
+};
+use super::history::play_previous;
+use super::prefetch::prefetch_queue;
+use super::voteskip::request_skip;
+use crate::Data;
+use crate::commands::config::{Refusal, check_user};
+use anyhow::anyhow;
+use poise::serenity_prelude as serenity;
+use serenity::{
+    ButtonStyle, ComponentInteraction, CreateActionRow, CreateButton, CreateInteractionResponse,
+    CreateInteractionResponseMessage,
+};
+use tracing::warn;
+
+/// Every button on the now playing message has a custom id starting with this
+pub(crate) const CONTROL_PREFIX: &str = "now_playing:";
+
+const PREVIOUS: &str = "now_playing:previous";
+const PAUSE: &str = "now_playing:pause";
+const NEXT: &str = "now_playing:next";
+const STOP: &str = "now_playing:stop";
+const LOOP: &str = "now_playing:loop";
+const SHUFFLE: &str = "now_playing:shuffle";
+const VOLUME_DOWN: &str = "now_playing:volume_down";
+const VOLUME_UP: &str = "now_playing:volume_up";
+
+/// How much one press of a volume button changes the volume, in percent
+const VOLUME_STEP: i16 = 10;
+
+/// Button rows attached to the now playing message
+pub(crate) fn now_playing_components() -> Vec<CreateActionRow> {
+    let button = |id: &str, emoji: char| {
+        CreateButton::new(id)
+            .emoji(emoji)
+            .style(ButtonStyle::Secondary)
+    };
+    vec![
+        CreateActionRow::Buttons(vec![
+            button(PREVIOUS, '⏮'),
+            button(PAUSE, '⏯'),
+            button(NEXT, '⏭'),
+            button(STOP, '⏹'),
+        ]),
+        CreateActionRow::Buttons(vec![
+            button(LOOP, '🔁'),
+            button(SHUFFLE, '🔀'),
+            button(VOLUME_DOWN, '🔉'),
+            button(VOLUME_UP, '🔊'),
+        ]),
+    ]
+}
+
+/// Name of the command a now playing button stands for
+fn control_command(custom_id: &str) -> &'static str {
+    match custom_id {
+        PREVIOUS => "previous",
+        PAUSE => "pause",
+        NEXT => "next",
+        STOP => "stop",
+        LOOP => "loop_toggle",
+        SHUFFLE => "shuffle",
+        VOLUME_DOWN | VOLUME_UP => "volume",
+        _ => "",
+    }
+}
+
+/// Handle a press of one of the now playing buttons, running the same logic as
+/// the matching command and refreshing the embed to show the new track state.
+/// Every press is answered, errors only to the one who pressed.
+pub(crate) async fn handle_control(
+    ctx: &serenity::Context,
+    data: &Data,
+    press: &ComponentInteraction,
+) -> anyhow::Result<()> {
+    let response = match control(ctx, data, press).await {
+        Ok(response) => response,
+        Err(err) => {
+            warn!("Error handling now playing control: {:?}", err);
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(err.to_string())
+                    .ephemeral(true),
+            )
+        }
+    };
+    press.create_response(ctx, response).await?;
+    Ok(())
+}
+
+/// Run the control pressed, returning the answer to the press
+async fn control(
+    ctx: &serenity::Context,
+    data: &Data,
+    press: &ComponentInteraction,
+) -> anyhow::Result<CreateInteractionResponse> {
+    let guild_id = press
+        .guild_id
+        .ok_or(anyhow!("Controls only work inside a guild"))?;
+    let manager = songbird::get(ctx).await.ok_or(anyhow!(
+        "Songbird Voice client placed in at initialisation."
+    ))?;
+
+    // The buttons follow the same rules as the commands they stand for
+    let settings = data.settings.get(guild_id).await;
+    let command = control_command(&press.data.custom_id);
+    match check_user(
+        ctx,
+        &settings,
+        guild_id,
+        press.channel_id,
+        press.user.id,
+        command,
+    )
+    .await
+    {
+        Ok(()) => {}
+        Err(Refusal::Blocked) => return Ok(CreateInteractionResponse::Acknowledge),
+        Err(Refusal::Reason(reason)) => return Err(anyhow!(reason)),
+    }
+    let handler_lock = manager.get(guild_id).ok_or(anyhow!("Nothing is playing"))?;
+    let mut handler = handler_lock.lock().await;
+
+    // Only people listening along get to press the buttons
+    let bot_channel = handler.current_channel().map(|chan| chan.0.get());
+    let user_channel = ctx
+        .cache
+        .guild(guild_id)
+        .and_then(|guild| guild.voice_states.get(&press.user.id)?.channel_id)
+        .map(|chan| chan.get());
+    if bot_channel.is_none() || bot_channel != user_channel {
+        return Err(anyhow!("Join my voice channel to use the controls"));
+    }
+
+    let session = data.sessions.get(guild_id).await;
+    match press.data.custom_id.as_str() {
+        PREVIOUS => {
+            play_previous(ctx, data, guild_id, &mut handler, press.user.id).await?;
+        }
+        PAUSE => toggle_pause(&handler).await?,
+        NEXT => {
+            let msg = request_skip(
+                ctx,
+                data,
+                guild_id,
+                press.channel_id,
+                &handler,
+                press.user.id,
+            )
+            .await?;
+            // Votes are counted in the open, a plain skip shows in the embed on its own
+            return Ok(if settings.vote_skip {
+                CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new().content(msg),
+                )
+            } else {
+                CreateInteractionResponse::Acknowledge
+            });
+        }
+        LOOP => {
+            set_loop_mode(&handler, &session, None).await?;
+        }
+        SHUFFLE => {
+            shuffle_queue(&handler, &session).await;
+            prefetch_queue(ctx, session.clone()).await;
+        }
+        VOLUME_DOWN => {
+            change_volume(&handler, &data.settings, guild_id, -VOLUME_STEP).await?;
+        }
+        VOLUME_UP => {
+            change_volume(&handler, &data.settings, guild_id, VOLUME_STEP).await?;
+        }
+        STOP => {
+            stop_playback(&handler, &session, &ctx.http, None).await;
+            // The stopped message has already been edited
+            return Ok(CreateInteractionResponse::Acknowledge);
+        }
+        id => return Err(anyhow!("Unknown control {}", id)),
+    }
+
+    // Skipping swaps the song, the song end notifier refreshes the embed for it
+    let cur_song = session.cur_song.lock().await.clone();
+    let cur_track = handler.queue().current();
+    Ok(match (cur_song, cur_track) {
+        (Some(cur_song), Some(cur_track)) if press.data.custom_id != PREVIOUS => {
+            let track_state = cur_track.get_info().await?;
+            let loop_mode = *session.loop_mode.lock().await;
+            let embed = create_now_playing_embed(&cur_song, &track_state, loop_mode).await;
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .embed(embed)
+                    .components(now_playing_components()),
+            )
+        }
+        _ => CreateInteractionResponse::Acknowledge,
+    })
+}
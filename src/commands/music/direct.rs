@@ -0,0 +1,193 @@
+use super::session::{QueuedItem, TrackInput};
+use super::sources::{ResolveContext, SourceResolver};
+use anyhow::{Context, anyhow};
+use poise::serenity_prelude::{self as serenity, UserId};
+use songbird::input::codecs::{get_codec_registry, get_probe};
+use songbird::input::{AuxMetadata, HttpRequest, Input, LiveInput, Parsed};
+use std::time::Duration;
+use symphonia::core::meta::{MetadataRevision, StandardTagKey, StandardVisualKey};
+use url::Url;
+
+/// File extensions played straight from a link, without going through yt-dlp
+pub(crate) const AUDIO_EXTENSIONS: [&str; 5] = ["mp3", "m4a", "flac", "ogg", "wav"];
+
+/// Whether the url points directly at an audio file we can decode
+pub(crate) fn is_audio_file_link(url_string: &str) -> bool {
+    let Ok(url) = Url::parse(url_string) else {
+        return false;
+    };
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return false;
+    }
+    file_extension(url.path()).is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.as_str()))
+}
+
+pub(crate) fn file_extension(path: &str) -> Option<String> {
+    let (_, ext) = path.rsplit_once('.')?;
+    Some(ext.to_ascii_lowercase())
+}
+
+/// Last part of the url path, used as the title when the file has no tags
+fn file_name(url_string: &str) -> Option<String> {
+    let url = Url::parse(url_string).ok()?;
+    let name = url.path_segments()?.next_back()?;
+    let name = match name.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => name,
+    };
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Queue a direct link to an audio file, reading its tags for the now playing embed
+pub(crate) async fn get_http_source(
+    http_client: reqwest::Client,
+    url: String,
+    requester: UserId,
+) -> anyhow::Result<QueuedItem> {
+    let request = HttpRequest::new(http_client, url.clone());
+    let mut metadata = probe_metadata(request.clone().into())
+        .await
+        .with_context(|| format!("Error reading the audio file at {}", url))?;
+    if metadata.title.is_none() {
+        metadata.title = file_name(&url);
+    }
+    metadata.source_url = Some(url);
+    Ok(QueuedItem {
+        input: TrackInput::Http(request),
+        metadata,
+        requester,
+        start: None,
+        live: false,
+    })
+}
+
+async fn parse(input: Input) -> anyhow::Result<Parsed> {
+    let input = input
+        .make_playable_async(get_codec_registry(), get_probe())
+        .await?;
+    match input {
+        Input::Live(LiveInput::Parsed(parsed), _) => Ok(parsed),
+        _ => Err(anyhow!("The audio file could not be parsed")),
+    }
+}
+
+/// Songs from direct links to audio files
+pub struct HttpResolver;
+
+#[serenity::async_trait]
+impl SourceResolver for HttpResolver {
+    fn can_handle(&self, url: &str) -> bool {
+        is_audio_file_link(url)
+    }
+
+    async fn resolve(&self, url: &str, ctx: &ResolveContext) -> anyhow::Result<Vec<QueuedItem>> {
+        let item = get_http_source(ctx.http_client.clone(), url.to_string(), ctx.requester).await?;
+        Ok(vec![item])
+    }
+}
+
+/// Open an input far enough to parse its container, and collect the duration
+/// and tags symphonia finds in it
+pub(crate) async fn probe_metadata(input: Input) -> anyhow::Result<AuxMetadata> {
+    let mut parsed = parse(input).await?;
+    Ok(read_metadata(&mut parsed))
+}
+
+/// Like [`probe_metadata`], also reading the embedded cover
+pub(crate) async fn probe_metadata_and_cover(
+    input: Input,
+) -> anyhow::Result<(AuxMetadata, Option<Cover>)> {
+    let mut parsed = parse(input).await?;
+    Ok((read_metadata(&mut parsed), read_cover(&mut parsed)))
+}
+
+/// Read the embedded cover of an input
+pub(crate) async fn probe_cover(input: Input) -> anyhow::Result<Option<Cover>> {
+    let mut parsed = parse(input).await?;
+    Ok(read_cover(&mut parsed))
+}
+
+fn read_metadata(parsed: &mut Parsed) -> AuxMetadata {
+    let mut metadata = AuxMetadata::default();
+    if let Some(track) = parsed.format.default_track() {
+        let params = &track.codec_params;
+        metadata.channels = params.channels.map(|channels| channels.count() as u8);
+        metadata.sample_rate = params.sample_rate;
+        if let (Some(time_base), Some(frames)) = (params.time_base, params.n_frames) {
+            let time = time_base.calc_time(frames);
+            metadata.duration =
+                Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac));
+        }
+    }
+    // Tags inside the container win over tags found around it, like ID3 on a wav file
+    if let Some(revision) = parsed.format.metadata().current() {
+        read_tags(&mut metadata, revision);
+    }
+    if let Some(revision) = parsed.meta.get().as_ref().and_then(|meta| meta.current()) {
+        read_tags(&mut metadata, revision);
+    }
+    metadata
+}
+
+/// Fill the fields of `metadata` that are still empty from a set of tags
+fn read_tags(metadata: &mut AuxMetadata, revision: &MetadataRevision) {
+    for tag in revision.tags() {
+        let field = match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => &mut metadata.title,
+            Some(StandardTagKey::Artist) | Some(StandardTagKey::AlbumArtist) => {
+                &mut metadata.artist
+            }
+            Some(StandardTagKey::Album) => &mut metadata.album,
+            Some(StandardTagKey::Date) | Some(StandardTagKey::ReleaseDate) => &mut metadata.date,
+            _ => continue,
+        };
+        if field.is_none() {
+            field.replace(tag.value.to_string());
+        }
+    }
+    if metadata.track.is_none() {
+        metadata.track = metadata.title.clone();
+    }
+}
+
+/// Picture embedded in an audio file, like an album cover
+pub(crate) struct Cover {
+    pub data: Vec<u8>,
+    pub media_type: String,
+}
+
+impl Cover {
+    /// File name to upload the cover as, Discord picks the image type from the extension
+    pub fn file_name(&self) -> String {
+        let ext = match self.media_type.as_str() {
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            _ => "jpg",
+        };
+        format!("cover.{}", ext)
+    }
+}
+
+/// Pick the front cover out of the pictures in a file, or any picture if it has none
+fn read_cover(parsed: &mut Parsed) -> Option<Cover> {
+    let mut visuals = Vec::new();
+    if let Some(revision) = parsed.format.metadata().current() {
+        visuals.extend(revision.visuals().iter().cloned());
+    }
+    if let Some(revision) = parsed.meta.get().as_ref().and_then(|meta| meta.current()) {
+        visuals.extend(revision.visuals().iter().cloned());
+    }
+    let front = visuals
+        .iter()
+        .position(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+        .unwrap_or(0);
+    if front >= visuals.len() {
+        return None;
+    }
+    let visual = visuals.swap_remove(front);
+    Some(Cover {
+        data: visual.data.into_vec(),
+        media_type: visual.media_type,
+    })
+}
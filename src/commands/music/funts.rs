@@ -2,19 +2,28 @@ use std::time::Duration;
 
 use crate::Context;
 use crate::Error;
-use crate::commands::music::common::join_n_get_voice_channel_handler;
+use crate::commands::music::common::{get_guild_session, join_n_get_voice_channel_handler};
+use crate::commands::music::helpers::parse_timestamp;
+use crate::commands::music::prefetch::prefetch_queue;
+use crate::commands::music::session::{GuildSession, LoopMode, QueuedItem};
+use crate::commands::music::voteskip::request_skip;
+use crate::settings::SettingsStore;
 use anyhow::Result;
 use anyhow::anyhow;
 use poise;
 use poise::serenity_prelude as serenity;
 use rand::seq::SliceRandom;
-use serenity::CreateEmbed;
+use serenity::{CreateEmbed, CreateEmbedFooter};
 
-use songbird::input::AuxMetadata;
+use serenity::EditMessage;
+use songbird::Call;
 use songbird::tracks::LoopState;
 use songbird::tracks::Queued;
 use songbird::tracks::{PlayMode, TrackState};
 
+/// Loudest volume in percent a song can be turned up to
+pub(crate) const MAX_VOLUME: u16 = 200;
+
 // async fn show_n_delete_msg(ctx: Context<'_>, msg: &str) -> anyhow::Result<()> {
 //     let msg = ctx.say(msg).await?;
 //     //tokio::time::sleep(Duration::from_secs(1)).await;
@@ -25,20 +34,30 @@ use songbird::tracks::{PlayMode, TrackState};
 #[poise::command(slash_command, prefix_command, guild_only, track_edits)]
 /// "Skip currently playing song in the queue"
 pub async fn next(ctx: Context<'_>) -> Result<(), Error> {
-    let _ = ctx.defer().await?;
+    ctx.defer().await?;
+    let msg;
     {
         let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
         let handler = handler_lock.lock().await;
-        handler.queue().skip()?;
+        let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+        msg = request_skip(
+            ctx.serenity_context(),
+            ctx.data(),
+            guild_id,
+            ctx.channel_id(),
+            &handler,
+            ctx.author().id,
+        )
+        .await?;
     }
     //    show_n_delete_msg(ctx, "song skipped").await?;
-    ctx.say("song skipped").await?;
+    ctx.say(msg).await?;
     Ok(())
 }
 #[poise::command(slash_command, prefix_command, guild_only, track_edits)]
 /// "Pause song in the queue"
 pub async fn pause(ctx: Context<'_>) -> Result<(), Error> {
-    let _ = ctx.defer().await?;
+    ctx.defer().await?;
     {
         let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
         //let handler_lock = get_current_guild_handler(&ctx).await?;
@@ -53,7 +72,7 @@ pub async fn pause(ctx: Context<'_>) -> Result<(), Error> {
 #[poise::command(slash_command, prefix_command, guild_only, track_edits)]
 ///"Resume song in the queue"
 pub async fn resume(ctx: Context<'_>) -> Result<(), Error> {
-    let _ = ctx.defer().await?;
+    ctx.defer().await?;
     {
         //let handler_lock = get_current_voice_chan_handler(&ctx).await?;
 
@@ -68,35 +87,235 @@ pub async fn resume(ctx: Context<'_>) -> Result<(), Error> {
 #[poise::command(slash_command, prefix_command, guild_only, track_edits)]
 ///"Shuffle the queue"
 pub async fn shuffle(ctx: Context<'_>) -> Result<(), Error> {
-    let _ = ctx.defer().await?;
+    ctx.defer().await?;
     {
         let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
         //let handler_lock = get_current_voice_chan_handler(&ctx).await?;
         let handler = handler_lock.lock().await;
+        let session = get_guild_session(&ctx).await?;
+        shuffle_queue(&handler, &session).await;
+        prefetch_queue(ctx.serenity_context(), session).await;
+    }
+    // show_n_delete_msg(ctx, "queue shuffled").await?;
+    ctx.say("Song shuffling turned on").await?;
+    Ok(())
+}
+
+pub(crate) async fn shuffle_queue(handler: &Call, session: &GuildSession) {
+    handler.queue().modify_queue(|q| {
+        let mut rng = rand::rng();
+        let mut vec: Vec<Queued> = q.drain(..).collect();
+        vec.shuffle(&mut rng);
+        q.extend(vec);
+    });
+    //this order is important as rng does not carry across await points
+    let mut queue_lock = session.queue.lock().await;
+    let mut rng = rand::rng();
+    queue_lock.make_contiguous().shuffle(&mut rng);
+}
 
-        handler.queue().modify_queue(|q| {
-            let mut rng = rand::rng();
-            let mut vec: Vec<Queued> = q.drain(..).collect();
-            vec.shuffle(&mut rng);
-            q.extend(vec);
-        });
+#[poise::command(slash_command, prefix_command, guild_only, track_edits)]
+///"Stop playing and clear the queue"
+pub async fn stop(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+    {
+        let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
+        let handler = handler_lock.lock().await;
+        let session = get_guild_session(&ctx).await?;
+        stop_playback(&handler, &session, ctx.http(), None).await;
     }
-    let data = ctx.data();
+    ctx.say("stopped playing").await?;
+    Ok(())
+}
+
+/// Stop the current song and drop the queue. The now playing message is left
+/// showing the stopped song, with `note` in its footer, and the next `play`
+/// posts a fresh one.
+pub(crate) async fn stop_playback(
+    handler: &Call,
+    session: &GuildSession,
+    http: &serenity::Http,
+    note: Option<&str>,
+) {
+    let mut stopped_state = match handler.queue().current() {
+        Some(cur_track) => cur_track.get_info().await.unwrap_or_default(),
+        None => TrackState::default(),
+    };
+    stopped_state.playing = PlayMode::Stop;
+    session.next_generation();
+    session.queue.lock().await.clear();
+    handler.queue().stop();
+    let cur_song = session.cur_song.lock().await.take();
+    if let (Some(mut msg), Some(cur_song)) = (session.now_playing_msg.lock().await.take(), cur_song)
     {
-        //this order is important as rng does not carry across await points
-        let mut queue_lock = data.queue.lock().await;
-        let mut rng = rand::rng();
-        queue_lock.shuffle(&mut rng);
+        let loop_mode = *session.loop_mode.lock().await;
+        let mut embed = create_now_playing_embed(&cur_song, &stopped_state, loop_mode).await;
+        if let Some(note) = note {
+            embed = embed.footer(CreateEmbedFooter::new(note));
+        }
+        let edit_builder = EditMessage::new().embed(embed).components(vec![]);
+        msg.edit(http, edit_builder).await.ok();
     }
-    // show_n_delete_msg(ctx, "queue shuffled").await?;
-    ctx.say("Song shuffling turned on").await?;
+}
+
+/// Pause the current song if it is playing, resume it otherwise
+pub(crate) async fn toggle_pause(handler: &Call) -> anyhow::Result<()> {
+    let cur_track_handle = handler
+        .queue()
+        .current()
+        .ok_or(anyhow!("Error getting current track"))?;
+    if cur_track_handle.get_info().await?.playing == PlayMode::Play {
+        handler.queue().pause()?;
+    } else {
+        handler.queue().resume()?;
+    }
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, guild_only, track_edits)]
+///"Jump to a time in the current song, like 1:30, +30s or -10s"
+pub async fn seek(
+    ctx: Context<'_>,
+    #[description = "Time like 1:30, or +30s / -10s to jump from the current position"]
+    position: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let new_position;
+    {
+        let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
+        let handler = handler_lock.lock().await;
+        let session = get_guild_session(&ctx).await?;
+        new_position = seek_song(&handler, &position).await?;
+        refresh_now_playing(&handler, &session, ctx.http()).await;
+    }
+    ctx.say(format!("jumped to {}", format_duration(new_position)))
+        .await?;
+    Ok(())
+}
+
+/// Seek the current song to an absolute time, or relative to the current
+/// position when prefixed with `+` or `-`, returning the new position
+pub(crate) async fn seek_song(handler: &Call, position: &str) -> anyhow::Result<Duration> {
+    let cur_track_handle = handler
+        .queue()
+        .current()
+        .ok_or(anyhow!("Error getting current track"))?;
+    let invalid = || {
+        anyhow!(
+            "`{}` is not a time, try something like 1:30, +30s or -10s",
+            position
+        )
+    };
+    let cur_position = cur_track_handle.get_info().await?.position;
+    let position = position.trim();
+    let target = if let Some(offset) = position.strip_prefix('+') {
+        cur_position.saturating_add(parse_timestamp(offset).ok_or_else(invalid)?)
+    } else if let Some(offset) = position.strip_prefix('-') {
+        cur_position.saturating_sub(parse_timestamp(offset).ok_or_else(invalid)?)
+    } else {
+        parse_timestamp(position).ok_or_else(invalid)?
+    };
+    Ok(cur_track_handle.seek_async(target).await?)
+}
+
+/// Redraw the now playing embed right away instead of waiting for the next progress update
+pub(crate) async fn refresh_now_playing(
+    handler: &Call,
+    session: &GuildSession,
+    http: &serenity::Http,
+) {
+    let Some(cur_track) = handler.queue().current() else {
+        return;
+    };
+    let Some(cur_song) = session.cur_song.lock().await.clone() else {
+        return;
+    };
+    let Ok(track_state) = cur_track.get_info().await else {
+        return;
+    };
+    let loop_mode = *session.loop_mode.lock().await;
+    let embed = create_now_playing_embed(&cur_song, &track_state, loop_mode).await;
+    if let Some(msg) = session.now_playing_msg.lock().await.as_mut() {
+        msg.edit(http, EditMessage::new().embed(embed)).await.ok();
+    }
+}
+
+#[poise::command(slash_command, prefix_command, guild_only, track_edits)]
+///"Show or set the volume of this server in percent"
+pub async fn volume(
+    ctx: Context<'_>,
+    #[description = "New volume from 0 to 200 percent"]
+    #[min = 0]
+    #[max = 200]
+    volume: Option<u16>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let settings = &ctx.data().settings;
+    let Some(volume) = volume else {
+        let volume = settings.get(guild_id).await.volume;
+        ctx.say(format!("volume is at {}%", volume)).await?;
+        return Ok(());
+    };
+    if volume > MAX_VOLUME {
+        return Err(anyhow!("Volume can go from 0 to {}%", MAX_VOLUME).into());
+    }
+    {
+        let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
+        let handler = handler_lock.lock().await;
+        set_volume(&handler, settings, guild_id, volume).await?;
+        let session = get_guild_session(&ctx).await?;
+        refresh_now_playing(&handler, &session, ctx.http()).await;
+    }
+    ctx.say(format!("volume set to {}%", volume)).await?;
     Ok(())
 }
+
+/// Save the volume of a guild and apply it to the current song right away,
+/// songs enqueued later pick it up from the settings
+pub(crate) async fn set_volume(
+    handler: &Call,
+    settings: &SettingsStore,
+    guild_id: serenity::GuildId,
+    volume: u16,
+) -> anyhow::Result<()> {
+    let volume = volume.min(MAX_VOLUME);
+    settings
+        .update(guild_id, |settings| settings.volume = volume)
+        .await?;
+    if let Some(cur_track_handle) = handler.queue().current() {
+        cur_track_handle.set_volume(volume_to_gain(volume))?;
+    }
+    Ok(())
+}
+
+/// Raise or lower the volume of a guild, returning the new volume
+pub(crate) async fn change_volume(
+    handler: &Call,
+    settings: &SettingsStore,
+    guild_id: serenity::GuildId,
+    delta: i16,
+) -> anyhow::Result<u16> {
+    let volume = settings
+        .get(guild_id)
+        .await
+        .volume
+        .saturating_add_signed(delta)
+        .min(MAX_VOLUME);
+    set_volume(handler, settings, guild_id, volume).await?;
+    Ok(volume)
+}
+
+/// Songbird volumes are a gain where 1.0 leaves the song unchanged
+pub(crate) fn volume_to_gain(volume: u16) -> f32 {
+    volume as f32 / 100.0
+}
+
 #[poise::command(slash_command, prefix_command, guild_only, track_edits)]
 ///"join a voice channel"
 pub async fn join(ctx: Context<'_>) -> Result<(), Error> {
-    let _ = ctx.defer().await?;
-    let _ = join_n_get_voice_channel_handler(&ctx).await?;
+    ctx.defer().await?;
+    join_n_get_voice_channel_handler(&ctx).await?;
     ctx.say("Joined voice channel").await?;
     Ok(())
 }
@@ -104,48 +323,88 @@ pub async fn join(ctx: Context<'_>) -> Result<(), Error> {
 #[poise::command(slash_command, prefix_command, guild_only, track_edits)]
 ///"Disconnect and leave"
 pub async fn disconnect(ctx: Context<'_>) -> Result<(), Error> {
-    let _ = ctx.defer().await?;
+    ctx.defer().await?;
 
-    let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
-    //let handler_lock = get_current_voice_chan_handler(&ctx).await?;
-    let mut handler = handler_lock.lock().await;
-    handler.leave().await?;
+    let manager = songbird::get(ctx.serenity_context()).await.ok_or(anyhow!(
+        "Songbird Voice client placed in at initialisation."
+    ))?;
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let session = get_guild_session(&ctx).await?;
+    leave_voice(&manager, &session, guild_id, ctx.http(), None).await?;
     ctx.say("left voice channel").await?;
     Ok(())
 }
+
+/// Stop playing, clear the queue and leave the voice channel of a guild,
+/// leaving `note` on the now playing message
+pub(crate) async fn leave_voice(
+    manager: &songbird::Songbird,
+    session: &GuildSession,
+    guild_id: serenity::GuildId,
+    http: &serenity::Http,
+    note: Option<&str>,
+) -> anyhow::Result<()> {
+    if let Some(alone_timer) = session.alone_timer.lock().await.take() {
+        alone_timer.abort();
+    }
+    if let Some(handler_lock) = manager.get(guild_id) {
+        stop_playback(&*handler_lock.lock().await, session, http, note).await;
+    }
+    manager.remove(guild_id).await?;
+    Ok(())
+}
 #[poise::command(slash_command, prefix_command, guild_only, track_edits)]
-///"loop the current queue"
+///"Loop the current song or the whole queue"
 pub async fn loop_toggle(
     ctx: Context<'_>,
-    #[description = "Loop count"] count: Option<usize>,
+    #[description = "Loop mode, switches to the next one when left out"] mode: Option<LoopMode>,
+    #[description = "Loop the current song this many times instead"] count: Option<usize>,
 ) -> Result<(), Error> {
-    let _ = ctx.defer().await?;
+    ctx.defer().await?;
     let loop_msg;
-    let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
-    //let handler_lock = get_current_voice_chan_handler(&ctx).await?;
-    let handler = handler_lock.lock().await;
+    {
+        let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
+        //let handler_lock = get_current_voice_chan_handler(&ctx).await?;
+        let handler = handler_lock.lock().await;
+        let session = get_guild_session(&ctx).await?;
+        loop_msg = match count {
+            Some(count) => loop_song_for(&handler, count)?,
+            None => set_loop_mode(&handler, &session, mode).await?,
+        };
+    }
+
+    ctx.say(loop_msg).await?;
+    Ok(())
+}
+
+/// Loop the current song `count` more times, whatever the loop mode
+fn loop_song_for(handler: &Call, count: usize) -> anyhow::Result<String> {
     let cur_track_handle = handler
         .queue()
         .current()
         .ok_or(anyhow!("Error getting current track"))?;
-    if let Some(count) = count {
-        cur_track_handle.loop_for(count)?;
-        loop_msg = format!("song looped for {} times", count);
-        // show_n_delete_msg(ctx, loop_msg.as_str()).await?;
-    } else {
-        if cur_track_handle.get_info().await?.loops == LoopState::Finite(0) {
+    cur_track_handle.loop_for(count)?;
+    Ok(format!("song looped for {} times", count))
+}
+
+/// Switch the guild to `mode`, or to the next mode without one. Track mode is
+/// songbird's own loop on the current track, queue mode is handled when a song ends.
+pub(crate) async fn set_loop_mode(
+    handler: &Call,
+    session: &GuildSession,
+    mode: Option<LoopMode>,
+) -> anyhow::Result<String> {
+    let mut loop_mode = session.loop_mode.lock().await;
+    let mode = mode.unwrap_or(loop_mode.next());
+    if let Some(cur_track_handle) = handler.queue().current() {
+        if mode == LoopMode::Track {
             cur_track_handle.enable_loop()?;
-            loop_msg = "loop enabled".to_string();
-            // show_n_delete_msg(ctx, "song looped").await?;
         } else {
-            loop_msg = "loop disabled".to_string();
-            // show_n_delete_msg(ctx, "song loop disabled").await?;
             cur_track_handle.disable_loop()?;
         }
     }
-
-    ctx.say(loop_msg).await?;
-    Ok(())
+    *loop_mode = mode;
+    Ok(format!("Loop mode: {}", mode.label()))
 }
 
 // #[poise::command(slash_command, prefix_command, guild_only, track_edits)]
@@ -175,8 +434,9 @@ pub async fn loop_toggle(
 /// This command shows the current playlist of songs in the queue
 pub async fn playlist(ctx: Context<'_>) -> Result<(), Error> {
     ctx.defer().await?;
+    let session = get_guild_session(&ctx).await?;
     {
-        let data = ctx.data().queue.lock().await;
+        let data = session.queue.lock().await;
         // let mut embed = CreateEmbed::new()
         //     .title("Current Playlist")
         //     .description("Remaining songs to play");
@@ -198,21 +458,28 @@ pub async fn playlist(ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
+/// Format a duration as MM:SS, or H:MM:SS once it runs past an hour
+pub(crate) fn format_duration(d: Duration) -> String {
+    let total_secs = d.as_secs();
+    let hours = total_secs / 3600;
+    let minutes = (total_secs % 3600) / 60;
+    let seconds = total_secs % 60;
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
 pub(crate) async fn create_now_playing_embed(
-    metadata: &AuxMetadata,   // Pass by reference to avoid unnecessary clones
+    item: &QueuedItem,        // Pass by reference to avoid unnecessary clones
     track_state: &TrackState, // Pass by reference
+    loop_mode: LoopMode,
 ) -> CreateEmbed {
+    let metadata = &item.metadata;
     let total_duration = metadata.duration.unwrap_or_default();
     let current_position = track_state.position;
 
-    // --- Helper to format Duration into MM:SS ---
-    let format_duration = |d: Duration| {
-        let total_secs = d.as_secs();
-        let minutes = total_secs / 60;
-        let seconds = total_secs % 60;
-        format!("{:02}:{:02}", minutes, seconds)
-    };
-
     let current_time_str = format_duration(current_position);
     let total_time_str = format_duration(total_duration);
 
@@ -220,9 +487,8 @@ pub(crate) async fn create_now_playing_embed(
     // Creates a text-based progress bar like: `[▬▬▬▬🔘─────]`
     let progress_bar = {
         let percentage = if total_duration.as_secs() > 0 {
-            (current_position.as_secs_f64() / total_duration.as_secs_f64())
-                .max(0.0) // Ensure percentage doesn't go below 0
-                .min(1.0) // Ensure percentage doesn't exceed 1
+            // Keep the percentage between 0 and 1
+            (current_position.as_secs_f64() / total_duration.as_secs_f64()).clamp(0.0, 1.0)
         } else {
             0.0 // Avoid division by zero if duration is 0
         };
@@ -277,11 +543,14 @@ pub(crate) async fn create_now_playing_embed(
 
     // --- Build the Embed ---
     let embed_title = format!("{} Now Playing", status_icon);
-    let embed_description = format!(
+    let mut embed_description = format!(
         "**{}**\n{}", // Title bold, artist on new line
         metadata.title.as_deref().unwrap_or("Unknown Title"),
         metadata.artist.as_deref().unwrap_or("Unknown Artist")
     );
+    if let Some(album) = &metadata.album {
+        embed_description.push_str(&format!(" • *{}*", album));
+    }
 
     CreateEmbed::new()
         .colour(0x1DB954) // Spotify Green, or choose your preferred color
@@ -295,11 +564,16 @@ pub(crate) async fn create_now_playing_embed(
         .description(embed_description)
         .field(
             "Progress",
-            // Display the progress bar and the time signature MM:SS / MM:SS
-            format!(
-                "{} `{} / {}`",
-                progress_bar, current_time_str, total_time_str
-            ),
+            if item.live {
+                // A live stream has no end to show progress towards
+                format!("🔴 **LIVE** `{}`", current_time_str)
+            } else {
+                // Display the progress bar and the time signature MM:SS / MM:SS
+                format!(
+                    "{} `{} / {}`",
+                    progress_bar, current_time_str, total_time_str
+                )
+            },
             false, // Make the field take the full width
         )
         // Optionally add more fields if needed, e.g., requested by, volume
@@ -308,6 +582,14 @@ pub(crate) async fn create_now_playing_embed(
             format!("{}%", (track_state.volume * 100.0) as u32),
             true,
         )
-        .field("Looping", format!("{:?}", track_state.loops), true)
+        .field(
+            "Looping",
+            match track_state.loops {
+                // Looping a song a set number of times goes on top of the mode
+                LoopState::Finite(count) if count > 0 => format!("🔂 {} more", count),
+                _ => loop_mode.label().to_string(),
+            },
+            true,
+        )
         .timestamp(serenity::Timestamp::now()) // Show when the embed was generated
 }
@@ -1,7 +1,12 @@
 use std::io::ErrorKind;
+use std::time::Duration;
 
+use super::session::{QueuedItem, TrackInput};
+use super::sources::{ResolveContext, SourceResolver};
+use crate::config::yt_dlp;
 use anyhow::Context;
-use songbird::input::{AudioStreamError, AuxMetadata, YoutubeDl};
+use poise::serenity_prelude::{self as serenity, UserId};
+use songbird::input::{AudioStreamError, AuxMetadata, Compose, YoutubeDl};
 use tokio::process::Command;
 use tracing::info;
 use url::Url;
@@ -29,24 +34,20 @@ pub(crate) fn is_youtube_link(url_string: &str) -> ParseYtLink {
                             && url.query_pairs().any(|(key, _)| key == "v")
                         {
                             return ParseYtLink::Song;
-                        } else if path.starts_with("/watch")
-                            && url.query_pairs().any(|(key, _)| key == "list")
-                        {
-                            return ParseYtLink::Playlist;
-                        } else if path.starts_with("/playlist")
+                        } else if (path.starts_with("/watch") || path.starts_with("/playlist"))
                             && url.query_pairs().any(|(key, _)| key == "list")
                         {
                             return ParseYtLink::Playlist;
+                        } else if path.starts_with("/live/") || path.ends_with("/live") {
+                            // `/@name/live` is whatever the channel is streaming right now
+                            return ParseYtLink::Live;
                         } else if path.starts_with("/channel/") {
                             return ParseYtLink::Channel;
                         } else if path.starts_with("/user/") {
                             return ParseYtLink::User;
-                        } else if path.starts_with("/c/") {
+                        } else if path.starts_with("/c/") || path.starts_with("/@") {
                             return ParseYtLink::Channel;
-                        } else if path.starts_with("/live/") {
-                            return ParseYtLink::Live;
-                        } else if path == "/shorts/{}" && path.split('/').count() == 3 {
-                            // Basic check for shorts, might need refinement
+                        } else if path.starts_with("/shorts/") && path.split('/').count() == 3 {
                             return ParseYtLink::Shorts;
                         }
                     } else if host == "youtu.be" {
@@ -54,57 +55,142 @@ pub(crate) fn is_youtube_link(url_string: &str) -> ParseYtLink {
                             return ParseYtLink::Song;
                         } else if path.starts_with("/live/") {
                             return ParseYtLink::Live;
-                        } else if path == "/shorts/{}" && path.split('/').count() == 3 {
-                            // Basic check for shorts, might need refinement
+                        } else if path.starts_with("/shorts/") && path.split('/').count() == 3 {
                             return ParseYtLink::Shorts;
                         }
                     }
                     return ParseYtLink::Song;
                 }
-                return ParseYtLink::NotYoutube;
+                ParseYtLink::NotYoutube
             } else {
-                return ParseYtLink::Search;
+                ParseYtLink::Search
             }
         }
 
-        Err(_) => return ParseYtLink::Search,
+        Err(_) => ParseYtLink::Search,
+    }
+}
+
+/// Parse a timestamp written as `1:23`, `1:02:03`, `83`, `83s` or `1m23s`,
+/// `None` when it is not one or too large to fit
+pub(crate) fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let timestamp = timestamp.trim();
+    if timestamp.is_empty() {
+        return None;
+    }
+    if timestamp.contains(':') {
+        let mut secs: u64 = 0;
+        for part in timestamp.split(':') {
+            secs = secs.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+        }
+        return Some(Duration::from_secs(secs));
+    }
+    // YouTube style `1h2m3s`, a bare number is seconds
+    let mut secs: u64 = 0;
+    let mut number = String::new();
+    for c in timestamp.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        secs = secs.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if !number.is_empty() {
+        secs = secs.checked_add(number.parse().ok()?)?;
+    }
+    Some(Duration::from_secs(secs))
+}
+
+/// Link to the uploads tab of a channel, yt-dlp lists the tabs themselves for a bare channel link
+fn channel_uploads_url(url_string: &str) -> String {
+    let Ok(mut url) = Url::parse(url_string) else {
+        return url_string.to_string();
+    };
+    let path = url.path().trim_end_matches('/').to_string();
+    let has_tab = ["/videos", "/streams", "/shorts"]
+        .iter()
+        .any(|tab| path.ends_with(tab));
+    if !has_tab {
+        url.set_path(&format!("{}/videos", path));
     }
+    url.to_string()
+}
+
+/// Start time linked by the `t` or `start` parameter of a YouTube url
+pub(crate) fn youtube_start_time(url_string: &str) -> Option<Duration> {
+    let url = Url::parse(url_string).ok()?;
+    let from_query = url
+        .query_pairs()
+        .find(|(key, _)| key == "t" || key == "start")
+        .and_then(|(_, value)| parse_timestamp(&value));
+    // Older share links put the time in the fragment, like `#t=1m30s`
+    from_query.or_else(|| parse_timestamp(url.fragment()?.strip_prefix("t=")?))
 }
 
 use serde::{Deserialize, Serialize};
 use serde_json;
 
-// #[derive(Serialize, Deserialize, Debug, PartialEq)]
-// pub struct Thumbnail {
-//     url: String,
-//     height: u32,
-//     width: u32,
-// }
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Thumbnail {
+    url: String,
+    height: Option<u32>,
+    width: Option<u32>,
+}
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct YoutubeVideo {
     //pub id: String, // Or u32/u64 based on your actual data type
     pub title: String,
     //pub description: Option<String>,
-    // pub thumbnails: Vec<Thumbnail>,
+    #[serde(default)]
+    pub thumbnails: Vec<Thumbnail>,
     //pub view_count: u64,
     pub url: String,
+    /// Length in seconds, missing for live streams and some private entries
+    pub duration: Option<f64>,
+    pub channel: Option<String>,
+    pub uploader: Option<String>,
+    /// Channel of the whole list, flat channel listings leave `channel` empty
+    #[serde(default)]
+    pub playlist_uploader: Option<String>,
     // Add other relevant fields based on the JSON structure
     // This is just a basic structure to show how to map fields
 }
 
+impl YoutubeVideo {
+    /// Build the metadata for a flat playlist entry without asking yt-dlp again
+    pub fn into_aux_metadata(self) -> AuxMetadata {
+        AuxMetadata {
+            title: Some(self.title),
+            artist: self.channel.or(self.uploader).or(self.playlist_uploader),
+            duration: self.duration.map(Duration::from_secs_f64),
+            // yt-dlp lists thumbnails from the smallest to the largest
+            thumbnail: self.thumbnails.into_iter().last().map(|t| t.url),
+            source_url: Some(self.url),
+            ..Default::default()
+        }
+    }
+}
+
 pub trait YoutubeDlExt<'a> {
-    async fn search_playlist(q: &str) -> anyhow::Result<Vec<YoutubeVideo>>;
+    async fn search_playlist(q: &str, extra_args: &[String]) -> anyhow::Result<Vec<YoutubeVideo>>;
 }
 impl<'a> YoutubeDlExt<'a> for YoutubeDl<'a> {
-    async fn search_playlist(q: &str) -> anyhow::Result<Vec<YoutubeVideo>> {
+    async fn search_playlist(q: &str, extra_args: &[String]) -> anyhow::Result<Vec<YoutubeVideo>> {
         let ytdl_args = ["-j", q, "--flat-playlist"];
-        let mut command = Command::new("yt-dlp");
-        let cmd = command.args(ytdl_args);
+        let mut command = Command::new(yt_dlp());
+        let cmd = command.args(ytdl_args).args(extra_args);
 
         let output = cmd.output().await.map_err(|e| {
             AudioStreamError::Fail(if e.kind() == ErrorKind::NotFound {
-                format!("could not find executable '{}' on path", "yt-dlp").into()
+                format!("could not find executable '{}' on path", yt_dlp()).into()
             } else {
                 Box::new(e)
             })
@@ -113,8 +199,7 @@ impl<'a> YoutubeDlExt<'a> for YoutubeDl<'a> {
         if !output.status.success() {
             print!("{:?}", cmd);
             print!(
-                "{} failed with non-zero status code: {}",
-                "yt-dlp",
+                "yt-dlp failed with non-zero status code: {}",
                 std::str::from_utf8(&output.stderr[..]).unwrap_or("<no error message>")
             );
             return Err(anyhow::anyhow!("Unsuceessful getting ouput"));
@@ -123,64 +208,149 @@ impl<'a> YoutubeDlExt<'a> for YoutubeDl<'a> {
         let output = output
             .stdout
             .split(|&b| b == b'\n')
-            .filter(|&x| (!x.is_empty()))
+            .filter(|&x| !x.is_empty())
             .map(serde_json::from_slice)
             .map(|x| x.context("Errr serializing youtube video entry").unwrap())
             .collect::<Vec<YoutubeVideo>>();
-        return Ok(output);
+        Ok(output)
     }
 }
 
-pub async fn get_yt_sources(
-    http_client: reqwest::Client,
-    url: String,
-) -> anyhow::Result<Vec<YoutubeDl<'static>>> {
-    info!("Play command called with URL: {}", url);
-
-    let url_type = is_youtube_link(&url);
-
-    println!("Parsed URL: {:?}", url_type);
-    let mut sources = Vec::new();
-    let gen_search_res = |aux_data: AuxMetadata| -> YoutubeDl<'_> {
-        info!("Found playlist file url as {:?}", aux_data);
-        YoutubeDl::new(
-            http_client.clone(),
-            aux_data
-                .source_url
-                .expect("Error getting source url from search aux data"),
-        )
-    };
+/// Songs from YouTube links, and YouTube searches for anything that is not a link
+pub struct YoutubeResolver;
 
-    match url_type {
-        ParseYtLink::Search => {
-            sources = YoutubeDl::new_search(http_client.clone(), url)
-                .search(Some(5))
-                .await
-                .context("Error searching for the song")?
-                .map(gen_search_res)
-                .collect()
-        }
-        ParseYtLink::Playlist => {
-            let playlist = YoutubeDl::search_playlist(&url)
-                .await
-                .context("Error getting playlist")?;
-            sources = playlist
-                .into_iter()
-                .map(|video| YoutubeDl::new(http_client.clone(), video.url))
-                .collect();
-
-            //      sources=YoutubeDl::new(http_client.clone(), url).search(Some(5)).await.context("Error searching youtube playlist")?.into_iter().map(gen_search_res).collect();
+impl YoutubeResolver {
+    fn search_item(
+        http_client: &reqwest::Client,
+        metadata: AuxMetadata,
+        requester: UserId,
+    ) -> QueuedItem {
+        info!("Found playlist file url as {:?}", metadata);
+        QueuedItem {
+            input: TrackInput::Youtube(YoutubeDl::new_ytdl_like(
+                yt_dlp(),
+                http_client.clone(),
+                metadata
+                    .source_url
+                    .clone()
+                    .expect("Error getting source url from search aux data"),
+            )),
+            metadata,
+            requester,
+            start: None,
+            live: false,
         }
+    }
+}
 
-        ParseYtLink::Song => {
-            sources.push(YoutubeDl::new(http_client.clone(), url));
-        }
-        _ => {
-            return Err(anyhow::anyhow!(
-                "Error parsing the url to download playlist"
-            ));
+#[serenity::async_trait]
+impl SourceResolver for YoutubeResolver {
+    fn can_handle(&self, url: &str) -> bool {
+        is_youtube_link(url) != ParseYtLink::NotYoutube
+    }
+
+    async fn resolve(&self, url: &str, ctx: &ResolveContext) -> anyhow::Result<Vec<QueuedItem>> {
+        info!("Play command called with URL: {}", url);
+
+        let url_type = is_youtube_link(url);
+
+        println!("Parsed URL: {:?}", url_type);
+        let http_client = &ctx.http_client;
+        let requester = ctx.requester;
+        let mut sources = Vec::new();
+
+        match url_type {
+            ParseYtLink::Search => {
+                // Only the top result, `play` offers the others to pick from first
+                sources =
+                    YoutubeDl::new_search_ytdl_like(yt_dlp(), http_client.clone(), url.to_string())
+                        .search(Some(1))
+                        .await
+                        .context("Error searching for the song")?
+                        .map(|metadata| Self::search_item(http_client, metadata, requester))
+                        .collect()
+            }
+            ParseYtLink::Playlist | ParseYtLink::Channel | ParseYtLink::User => {
+                let max_tracks = ctx.settings.max_enqueue.max(1);
+                let playlist = if url_type == ParseYtLink::Playlist {
+                    let args = [
+                        "--playlist-items".to_string(),
+                        ctx.playlist.playlist_items(max_tracks),
+                    ];
+                    YoutubeDl::search_playlist(url, &args)
+                        .await
+                        .context("Error getting playlist")?
+                } else {
+                    // The newest uploads come first, a plain channel link queues the latest few
+                    let mut options = ctx.playlist;
+                    options.limit = options.limit.or(Some(ctx.settings.channel_uploads));
+                    let args = [
+                        "--playlist-items".to_string(),
+                        options.playlist_items(max_tracks),
+                    ];
+                    YoutubeDl::search_playlist(&channel_uploads_url(url), &args)
+                        .await
+                        .context("Error getting the channel uploads")?
+                };
+                sources = playlist
+                    .into_iter()
+                    .map(|video| QueuedItem {
+                        input: TrackInput::Youtube(YoutubeDl::new_ytdl_like(
+                            yt_dlp(),
+                            http_client.clone(),
+                            video.url.clone(),
+                        )),
+                        metadata: video.into_aux_metadata(),
+                        requester,
+                        start: None,
+                        live: false,
+                    })
+                    .collect();
+            }
+            ParseYtLink::Song | ParseYtLink::Shorts | ParseYtLink::Live => {
+                let start = youtube_start_time(url);
+                let mut input =
+                    YoutubeDl::new_ytdl_like(yt_dlp(), http_client.clone(), url.to_string());
+                let metadata = input
+                    .aux_metadata()
+                    .await
+                    .context("Error getting metadata from the input")?;
+                // yt-dlp reports no duration for a stream that is live right now
+                let live = url_type == ParseYtLink::Live || metadata.duration.is_none();
+                sources.push(QueuedItem {
+                    input: TrackInput::Youtube(input),
+                    metadata,
+                    requester,
+                    start: start.filter(|_| !live),
+                    live,
+                });
+            }
+            ParseYtLink::NotYoutube => {
+                return Err(anyhow::anyhow!(
+                    "Error parsing the url to download playlist"
+                ));
+            }
         }
+
+        Ok(sources)
     }
 
-    return Ok(sources);
+    async fn search(
+        &self,
+        query: &str,
+        ctx: &ResolveContext,
+        limit: usize,
+    ) -> anyhow::Result<Vec<QueuedItem>> {
+        // Links and prefixed queries like `local:` are not searched on YouTube
+        if query.trim().len() < 3 || Url::parse(query).is_ok() {
+            return Ok(Vec::new());
+        }
+        Ok(
+            YoutubeDl::new_search_ytdl_like(yt_dlp(), ctx.http_client.clone(), query.to_string())
+                .search(Some(limit))
+                .await?
+                .map(|metadata| Self::search_item(&ctx.http_client, metadata, ctx.requester))
+                .collect(),
+        )
+    }
 }
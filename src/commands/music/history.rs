@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::add::{play_sources, start_playing};
+use super::common::{get_guild_session, join_n_get_voice_channel_handler};
+use super::queue::describe_item;
+use super::session::{PlayedTrack, QueuedItem};
+use crate::commands::config::{Refusal, check_user};
+use crate::{Context, Data, Error};
+use anyhow::anyhow;
+use poise::CreateReply;
+use poise::serenity_prelude as serenity;
+use serenity::{
+    ComponentInteractionCollector, CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage, GuildId, UserId,
+};
+use songbird::Call;
+
+/// Number of recent plays listed by the history command
+const HISTORY_PAGE: usize = 10;
+/// Discord fits at most five buttons in a row
+const BUTTONS_PER_ROW: usize = 5;
+/// How long the re-queue buttons keep working after the last press
+const HISTORY_TIMEOUT: Duration = Duration::from_secs(600);
+
+fn title(item: &QueuedItem) -> &str {
+    item.metadata.title.as_deref().unwrap_or("Unknown Title")
+}
+
+/// Play the song before the current one again for `requester`, returning its
+/// title. Songs played again this way are passed over, so every call walks
+/// one song further back through the history.
+pub(crate) async fn play_previous(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: GuildId,
+    handler: &mut Call,
+    requester: UserId,
+) -> anyhow::Result<String> {
+    let session = data.sessions.get(guild_id).await;
+    let playing = handler.queue().current().is_some();
+    let (steps_back, mut item) = {
+        let history = session.history.lock().await;
+        let no_song = || anyhow!("No song was played before this one");
+        // The song playing right now is the newest entry of the history
+        let newest = history.front().ok_or_else(no_song)?;
+        let steps_back = newest.steps_back.unwrap_or(0) + usize::from(playing);
+        let item = history
+            .iter()
+            .filter(|played| played.steps_back.is_none())
+            .nth(steps_back)
+            .map(|played| played.item.clone())
+            .ok_or_else(no_song)?;
+        (steps_back, item)
+    };
+    item.requester = requester;
+    let title = title(&item).to_string();
+    session.rewind.lock().await.replace(steps_back);
+    if playing {
+        // Ending the current track hands the front of the queue to songbird
+        session.queue.lock().await.push_front(item);
+        handler.queue().skip()?;
+    } else {
+        start_playing(ctx, data, guild_id, handler, session, item).await?;
+    }
+    Ok(title)
+}
+
+#[poise::command(slash_command, prefix_command, guild_only)]
+/// Play the song before the current one again, each use goes one song further back
+pub async fn previous(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
+    let title = play_previous(
+        ctx.serenity_context(),
+        ctx.data(),
+        guild_id,
+        &mut *handler_lock.lock().await,
+        ctx.author().id,
+    )
+    .await?;
+    ctx.say(format!("Playing **{}** again", title)).await?;
+    Ok(())
+}
+
+fn history_embed(played: &[PlayedTrack]) -> CreateEmbed {
+    let description = if played.is_empty() {
+        "Nothing has been played yet.".to_string()
+    } else {
+        played
+            .iter()
+            .enumerate()
+            .map(|(index, played)| {
+                format!(
+                    "**{}.** {} <t:{}:R>",
+                    index + 1,
+                    describe_item(&played.item),
+                    played.played_at.unix_timestamp()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    CreateEmbed::new()
+        .colour(0x1DB954)
+        .title("Recently played")
+        .description(description)
+        .footer(CreateEmbedFooter::new(
+            "Press a number to queue that song again",
+        ))
+}
+
+#[poise::command(slash_command, prefix_command, guild_only)]
+/// Show the songs played recently, with buttons to queue them again
+pub async fn history(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let session = get_guild_session(&ctx).await?;
+    let played: Vec<PlayedTrack> = session
+        .history
+        .lock()
+        .await
+        .iter()
+        .take(HISTORY_PAGE)
+        .cloned()
+        .collect();
+    let ctx_id = ctx.id();
+    let buttons: Vec<CreateButton> = (1..=played.len())
+        .map(|number| {
+            CreateButton::new(format!("{}requeue{}", ctx_id, number)).label(number.to_string())
+        })
+        .collect();
+    let rows = buttons
+        .chunks(BUTTONS_PER_ROW)
+        .map(|row| CreateActionRow::Buttons(row.to_vec()))
+        .collect();
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(history_embed(&played))
+                .components(rows),
+        )
+        .await?;
+    if played.is_empty() {
+        return Ok(());
+    }
+
+    let requeue_prefix = format!("{}requeue", ctx_id);
+    while let Some(press) = ComponentInteractionCollector::new(ctx)
+        .filter(move |press| press.data.custom_id.starts_with(&ctx_id.to_string()))
+        .timeout(HISTORY_TIMEOUT)
+        .await
+    {
+        let Some(mut item) = press
+            .data
+            .custom_id
+            .strip_prefix(&requeue_prefix)
+            .and_then(|number| number.parse::<usize>().ok())
+            .and_then(|number| played.get(number.checked_sub(1)?))
+            .map(|played| played.item.clone())
+        else {
+            continue;
+        };
+        item.requester = press.user.id;
+        // Queueing a song again follows the same rules as `play`
+        let settings = ctx.data().settings.get(guild_id).await;
+        let allowed = check_user(
+            ctx.serenity_context(),
+            &settings,
+            guild_id,
+            press.channel_id,
+            press.user.id,
+            "play",
+        )
+        .await;
+        let result = match allowed {
+            Ok(()) => requeue(ctx, guild_id, item).await,
+            Err(Refusal::Blocked) => {
+                press
+                    .create_response(
+                        ctx.serenity_context(),
+                        CreateInteractionResponse::Acknowledge,
+                    )
+                    .await?;
+                continue;
+            }
+            Err(Refusal::Reason(reason)) => Err(anyhow!(reason)),
+        };
+        let response = match result {
+            Ok(title) => CreateInteractionResponseMessage::new()
+                .content(format!("<@{}> queued **{}** again", press.user.id, title)),
+            Err(err) => CreateInteractionResponseMessage::new()
+                .content(err.to_string())
+                .ephemeral(true),
+        };
+        press
+            .create_response(
+                ctx.serenity_context(),
+                CreateInteractionResponse::Message(response),
+            )
+            .await?;
+    }
+
+    // The buttons timed out, drop them so nobody presses dead ones
+    reply
+        .edit(ctx, CreateReply::default().components(vec![]))
+        .await?;
+    Ok(())
+}
+
+/// Queue a song from the history again, returning its title
+async fn requeue(ctx: Context<'_>, guild_id: GuildId, item: QueuedItem) -> anyhow::Result<String> {
+    let title = title(&item).to_string();
+    // Re-queueing needs the bot in a voice channel already, the presser may not be in one
+    let handler_lock = songbird::get(ctx.serenity_context())
+        .await
+        .and_then(|manager| manager.get(guild_id))
+        .ok_or(anyhow!(
+            "I'm not in a voice channel, use `play` to bring me in first"
+        ))?;
+    play_sources(ctx, handler_lock, VecDeque::from([item]), true).await?;
+    Ok(title)
+}
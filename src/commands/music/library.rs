@@ -0,0 +1,242 @@
+use super::direct::{AUDIO_EXTENSIONS, file_extension, probe_cover, probe_metadata_and_cover};
+use super::session::{QueuedItem, TrackInput};
+use super::sources::{ResolveContext, SourceResolver};
+use crate::{Context, Error};
+use anyhow::anyhow;
+use poise::serenity_prelude::{self as serenity, CreateAttachment, UserId};
+use songbird::input::{AuxMetadata, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// `play` queries starting with this search the local library instead of YouTube
+pub(crate) const LOCAL_PREFIX: &str = "local:";
+
+/// A file in the local library with the tags read when it was scanned
+#[derive(Clone, Debug)]
+pub struct LocalTrack {
+    pub path: PathBuf,
+    /// Path below the library folder, unique and shown in autocomplete
+    pub relative: String,
+    pub metadata: AuxMetadata,
+}
+
+impl LocalTrack {
+    fn matches(&self, words: &[String]) -> bool {
+        let haystack = format!(
+            "{} {} {} {}",
+            self.metadata.title.as_deref().unwrap_or_default(),
+            self.metadata.artist.as_deref().unwrap_or_default(),
+            self.metadata.album.as_deref().unwrap_or_default(),
+            self.relative
+        )
+        .to_lowercase();
+        words.iter().all(|word| haystack.contains(word))
+    }
+
+    pub fn to_queued_item(&self, requester: UserId) -> QueuedItem {
+        let mut metadata = self.metadata.clone();
+        metadata.source_url = Some(format!("{}{}", LOCAL_PREFIX, self.relative));
+        QueuedItem {
+            input: TrackInput::File(self.path.clone()),
+            metadata,
+            requester,
+            start: None,
+            live: false,
+        }
+    }
+}
+
+/// Index of the audio files in the music folder on the bot host
+pub struct Library {
+    root: Option<PathBuf>,
+    tracks: RwLock<Vec<LocalTrack>>,
+}
+
+impl Library {
+    /// An empty library for the folder at `root`, nothing is indexed until [`Library::scan`]
+    pub fn new(root: Option<PathBuf>) -> Self {
+        Self {
+            root,
+            tracks: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Read the tags of every audio file below the music folder, replacing the index
+    pub async fn scan(&self) -> anyhow::Result<usize> {
+        let root = self.root.clone().ok_or(anyhow!(
+            "No music folder is set, set `music_dir` in the config file, MUSIC_DIR or --music-dir"
+        ))?;
+        let walk_root = root.clone();
+        let mut paths = tokio::task::spawn_blocking(move || {
+            let mut paths = Vec::new();
+            find_audio_files(&walk_root, &mut paths);
+            paths
+        })
+        .await?;
+        paths.sort();
+
+        let mut tracks = Vec::with_capacity(paths.len());
+        for path in paths {
+            match read_local_track(&root, path.clone()).await {
+                Ok(track) => tracks.push(track),
+                Err(err) => warn!("Error reading tags of {}: {:?}", path.display(), err),
+            }
+        }
+        let count = tracks.len();
+        *self.tracks.write().await = tracks;
+        info!("Indexed {} songs in {}", count, root.display());
+        Ok(count)
+    }
+
+    /// Tracks whose tags or path contain every word of the query, exact titles first
+    pub async fn search(&self, query: &str, limit: usize) -> Vec<LocalTrack> {
+        let query = query.trim().to_lowercase();
+        let words: Vec<String> = query.split_whitespace().map(str::to_string).collect();
+        let tracks = self.tracks.read().await;
+        let mut found: Vec<&LocalTrack> = tracks
+            .iter()
+            .filter(|track| {
+                track.relative.to_lowercase() == query
+                    || track.path.to_string_lossy().to_lowercase() == query
+                    || track.matches(&words)
+            })
+            .collect();
+        found.sort_by_key(|track| {
+            let title = track.metadata.title.as_deref().unwrap_or_default();
+            (
+                track.relative.to_lowercase() != query,
+                title.to_lowercase() != query,
+            )
+        });
+        found.into_iter().take(limit).cloned().collect()
+    }
+
+    /// Best match for a `local:` query
+    pub async fn find(&self, query: &str, requester: UserId) -> anyhow::Result<QueuedItem> {
+        if self.root.is_none() {
+            return Err(anyhow!("There is no local music library on this bot"));
+        }
+        self.search(query, 1)
+            .await
+            .first()
+            .map(|track| track.to_queued_item(requester))
+            .ok_or(anyhow!("Nothing in the local library matches `{}`", query))
+    }
+}
+
+/// Songs from the local library, for `local:` queries
+pub struct LocalResolver {
+    library: Arc<Library>,
+}
+
+impl LocalResolver {
+    pub fn new(library: Arc<Library>) -> Self {
+        Self { library }
+    }
+}
+
+#[serenity::async_trait]
+impl SourceResolver for LocalResolver {
+    fn can_handle(&self, url: &str) -> bool {
+        url.starts_with(LOCAL_PREFIX)
+    }
+
+    async fn resolve(&self, url: &str, ctx: &ResolveContext) -> anyhow::Result<Vec<QueuedItem>> {
+        let query = url.strip_prefix(LOCAL_PREFIX).unwrap_or(url);
+        Ok(vec![self.library.find(query, ctx.requester).await?])
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        ctx: &ResolveContext,
+        limit: usize,
+    ) -> anyhow::Result<Vec<QueuedItem>> {
+        let query = query.strip_prefix(LOCAL_PREFIX).unwrap_or(query);
+        Ok(self
+            .library
+            .search(query, limit)
+            .await
+            .iter()
+            .map(|track| track.to_queued_item(ctx.requester))
+            .collect())
+    }
+}
+
+fn find_audio_files(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Error reading music folder {}: {:?}", dir.display(), err);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            find_audio_files(&path, paths);
+        } else if path.is_dir() {
+            // Linked folders are skipped, a link back up the tree would never end
+            info!("Skipping linked folder {}", path.display());
+        } else if path
+            .to_str()
+            .and_then(file_extension)
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.as_str()))
+        {
+            paths.push(path);
+        }
+    }
+}
+
+async fn read_local_track(root: &Path, path: PathBuf) -> anyhow::Result<LocalTrack> {
+    let (mut metadata, cover) = probe_metadata_and_cover(File::new(path.clone()).into()).await?;
+    if metadata.title.is_none() {
+        metadata.title = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned());
+    }
+    // The cover is uploaded along with the now playing message
+    metadata.thumbnail = cover.map(|cover| format!("attachment://{}", cover.file_name()));
+    let relative = path
+        .strip_prefix(root)
+        .unwrap_or(&path)
+        .to_string_lossy()
+        .into_owned();
+    Ok(LocalTrack {
+        path,
+        relative,
+        metadata,
+    })
+}
+
+/// Embedded cover of a local song, to upload next to the now playing embed
+pub(crate) async fn cover_attachment(item: &QueuedItem) -> Option<CreateAttachment> {
+    let TrackInput::File(path) = &item.input else {
+        return None;
+    };
+    match probe_cover(File::new(path.clone()).into()).await {
+        Ok(cover) => cover.map(|cover| {
+            let file_name = cover.file_name();
+            CreateAttachment::bytes(cover.data, file_name)
+        }),
+        Err(err) => {
+            warn!("Error reading cover of {}: {:?}", path.display(), err);
+            None
+        }
+    }
+}
+
+#[poise::command(slash_command, prefix_command, owners_only)]
+/// Scan the local music folder again to pick up new files
+pub async fn rescan(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+    let count = ctx.data().library.scan().await?;
+    ctx.say(format!("The local library has {} songs", count))
+        .await?;
+    Ok(())
+}
@@ -4,10 +4,23 @@
 // pub mod nowplaying;
 // pub mod pause;
 pub mod add;
+pub mod attachments;
+pub mod autoleave;
 pub use add::play;
 mod common;
-mod helpers;
-// pub mod queue;
+pub mod controls;
+pub mod direct;
+pub mod helpers;
+pub mod history;
+pub mod library;
+pub mod persist;
+mod picker;
+mod prefetch;
+pub mod queue;
+pub mod radio;
+pub mod session;
+pub mod sources;
+pub mod voteskip;
 // pub mod resume;
 // pub mod shuffle;
 pub mod funts;
@@ -0,0 +1,501 @@
+use super::add::{get_http_client, start_playing};
+use super::direct::is_audio_file_link;
+use super::helpers::{ParseYtLink, is_youtube_link};
+use super::library::LOCAL_PREFIX;
+use super::radio::stream_input;
+use super::session::{LoopMode, QueuedItem, Sessions, TrackInput};
+use crate::Data;
+use crate::commands::config::{Refusal, allowed_channels, check_user};
+use crate::config::yt_dlp;
+use crate::settings::write_json;
+use anyhow::{Context, anyhow};
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    ButtonStyle, ChannelId, ComponentInteraction, CreateActionRow, CreateButton, CreateEmbed,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    EditInteractionResponse, GuildId, UserId,
+};
+use songbird::Songbird;
+use songbird::input::{AuxMetadata, HttpRequest, YoutubeDl};
+use songbird::tracks::LoopState;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// How often the queues of all guilds are written to disk
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Every button on a resume offer has a custom id starting with this
+pub(crate) const RESUME_PREFIX: &str = "resume:";
+
+const RESUME: &str = "resume:accept";
+const DISMISS: &str = "resume:dismiss";
+
+/// A song saved to disk, enough to look it up again after a restart
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedTrack {
+    pub url: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    #[serde(default)]
+    pub album: Option<String>,
+    pub channel: Option<String>,
+    pub duration_secs: Option<f64>,
+    pub thumbnail: Option<String>,
+    pub requester: UserId,
+    /// Where playback continues, the play position for the current song
+    pub start_secs: Option<f64>,
+    #[serde(default)]
+    pub live: bool,
+}
+
+impl SavedTrack {
+    fn from_item(item: &QueuedItem, start: Option<Duration>) -> Option<Self> {
+        let metadata = &item.metadata;
+        let url = match &item.input {
+            TrackInput::File(path) => format!("{}{}", LOCAL_PREFIX, path.display()),
+            _ => metadata.source_url.clone()?,
+        };
+        Some(Self {
+            url,
+            title: metadata.title.clone(),
+            artist: metadata.artist.clone(),
+            album: metadata.album.clone(),
+            channel: metadata.channel.clone(),
+            duration_secs: metadata.duration.map(|duration| duration.as_secs_f64()),
+            thumbnail: metadata.thumbnail.clone(),
+            requester: item.requester,
+            start_secs: start.map(|start| start.as_secs_f64()),
+            live: item.live,
+        })
+    }
+
+    fn into_item(self, http_client: reqwest::Client) -> QueuedItem {
+        // Local songs are saved by their full path
+        let input = if let Some(path) = self.url.strip_prefix(LOCAL_PREFIX) {
+            TrackInput::File(PathBuf::from(path))
+        } else if self.live && is_youtube_link(&self.url) == ParseYtLink::NotYoutube {
+            stream_input(http_client, self.url.clone())
+        } else if is_audio_file_link(&self.url) {
+            TrackInput::Http(HttpRequest::new(http_client, self.url.clone()))
+        } else {
+            TrackInput::Youtube(YoutubeDl::new_ytdl_like(
+                yt_dlp(),
+                http_client,
+                self.url.clone(),
+            ))
+        };
+        let metadata = AuxMetadata {
+            title: self.title,
+            artist: self.artist,
+            album: self.album,
+            channel: self.channel,
+            duration: self.duration_secs.map(Duration::from_secs_f64),
+            thumbnail: self.thumbnail,
+            source_url: Some(self.url),
+            ..Default::default()
+        };
+        QueuedItem {
+            input,
+            metadata,
+            requester: self.requester,
+            start: self.start_secs.map(Duration::from_secs_f64),
+            live: self.live,
+        }
+    }
+}
+
+/// Loop state of the current song, songbird's `LoopState` cannot be serialized
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub enum SavedLoop {
+    Infinite,
+    Finite(usize),
+    #[default]
+    Off,
+}
+
+impl From<LoopState> for SavedLoop {
+    fn from(loops: LoopState) -> Self {
+        match loops {
+            LoopState::Infinite => Self::Infinite,
+            LoopState::Finite(0) => Self::Off,
+            LoopState::Finite(count) => Self::Finite(count),
+        }
+    }
+}
+
+/// Everything needed to pick playback up again in a guild
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedSession {
+    pub voice_channel: ChannelId,
+    pub text_channel: ChannelId,
+    pub current: Option<SavedTrack>,
+    pub queue: Vec<SavedTrack>,
+    #[serde(default)]
+    pub loops: SavedLoop,
+    #[serde(default)]
+    pub loop_mode: LoopMode,
+}
+
+/// Queues saved at shutdown, offered for resuming once the bot is back
+pub struct QueueStore {
+    path: PathBuf,
+    /// Sessions saved by the last run that nobody resumed or dismissed yet
+    offers: Mutex<HashMap<GuildId, SavedSession>>,
+}
+
+impl QueueStore {
+    /// Load the queues saved at `path`, starting empty if nothing was saved yet
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let offers = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Error parsing queue file {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Error reading queue file {}", path.display()));
+            }
+        };
+        Ok(Self {
+            path,
+            offers: Mutex::new(offers),
+        })
+    }
+
+    /// Write the sessions playing right now to disk, along with the offers
+    /// still open. Guilds that started playing again drop their old offer.
+    pub async fn save(&self, playing: HashMap<GuildId, SavedSession>) -> anyhow::Result<()> {
+        let mut offers = self.offers.lock().await;
+        offers.retain(|guild_id, _| !playing.contains_key(guild_id));
+        let mut saved = offers.clone();
+        saved.extend(playing);
+        write_json(&self.path, &saved).await
+    }
+
+    async fn offers(&self) -> Vec<(GuildId, SavedSession)> {
+        self.offers
+            .lock()
+            .await
+            .iter()
+            .map(|(guild_id, saved)| (*guild_id, saved.clone()))
+            .collect()
+    }
+
+    /// Voice channel a resume offer would rejoin
+    async fn offer_channel(&self, guild_id: GuildId) -> Option<ChannelId> {
+        self.offers
+            .lock()
+            .await
+            .get(&guild_id)
+            .map(|saved| saved.voice_channel)
+    }
+
+    /// Remove the offer of a guild, returning it if there was one
+    async fn take_offer(&self, guild_id: GuildId) -> anyhow::Result<Option<SavedSession>> {
+        let mut offers = self.offers.lock().await;
+        let offer = offers.remove(&guild_id);
+        if offer.is_some() {
+            write_json(&self.path, &*offers).await?;
+        }
+        Ok(offer)
+    }
+}
+
+/// Snapshot the queue of every guild the bot is playing in
+pub(crate) async fn snapshot_all(
+    manager: &Songbird,
+    sessions: &Sessions,
+) -> HashMap<GuildId, SavedSession> {
+    let mut playing = HashMap::new();
+    for (guild_id, session) in sessions.all().await {
+        let Some(handler_lock) = manager.get(guild_id) else {
+            continue;
+        };
+        let (voice_channel, cur_track) = {
+            let handler = handler_lock.lock().await;
+            let Some(voice_channel) = handler.current_channel() else {
+                continue;
+            };
+            (
+                ChannelId::new(voice_channel.0.get()),
+                handler.queue().current(),
+            )
+        };
+        let Some(text_channel) = *session.text_channel.lock().await else {
+            continue;
+        };
+        let track_state = match cur_track {
+            Some(track) => track.get_info().await.ok(),
+            None => None,
+        };
+        // Only a song songbird still has is current, a finished one is not resumed
+        let current = session
+            .cur_song
+            .lock()
+            .await
+            .as_ref()
+            .filter(|_| track_state.is_some())
+            .and_then(|item| {
+                // A live stream carries on without us, there is no position to go back to
+                let position = track_state
+                    .as_ref()
+                    .filter(|_| !item.live)
+                    .map(|state| state.position);
+                SavedTrack::from_item(item, position)
+            });
+        let queue: Vec<SavedTrack> = session
+            .queue
+            .lock()
+            .await
+            .iter()
+            .filter_map(|item| SavedTrack::from_item(item, item.start))
+            .collect();
+        if current.is_none() && queue.is_empty() {
+            continue;
+        }
+        playing.insert(
+            guild_id,
+            SavedSession {
+                voice_channel,
+                text_channel,
+                current,
+                queue,
+                loops: track_state
+                    .map(|state| state.loops.into())
+                    .unwrap_or_default(),
+                loop_mode: *session.loop_mode.lock().await,
+            },
+        );
+    }
+    playing
+}
+
+/// Save the queues every now and then, so a crash loses at most a minute
+pub(crate) async fn save_periodically(
+    ctx: serenity::Context,
+    sessions: Arc<Sessions>,
+    queues: Arc<QueueStore>,
+) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(manager) = songbird::get(&ctx).await else {
+            continue;
+        };
+        let playing = snapshot_all(&manager, &sessions).await;
+        if let Err(err) = queues.save(playing).await {
+            warn!("Error saving queues: {:?}", err);
+        }
+    }
+}
+
+/// Ask every guild that was playing before the restart whether to continue
+pub(crate) async fn offer_resume(http: &serenity::Http, queues: &QueueStore) {
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(RESUME)
+            .label("Resume")
+            .style(ButtonStyle::Success),
+        CreateButton::new(DISMISS)
+            .label("Dismiss")
+            .style(ButtonStyle::Secondary),
+    ]);
+    for (guild_id, saved) in queues.offers().await {
+        let song_count = saved.queue.len() + usize::from(saved.current.is_some());
+        let mut description = format!(
+            "I was restarted while playing {} songs in <#{}>.",
+            song_count, saved.voice_channel
+        );
+        if let Some(current) = &saved.current {
+            description.push_str(&format!(
+                "\nNow playing was **{}**",
+                current.title.as_deref().unwrap_or("Unknown Title")
+            ));
+        }
+        let embed = CreateEmbed::new()
+            .colour(0x1DB954)
+            .title("Resume playback?")
+            .description(description);
+        let msg = CreateMessage::new()
+            .embed(embed)
+            .components(vec![buttons.clone()]);
+        if let Err(err) = saved.text_channel.send_message(http, msg).await {
+            warn!(
+                "Error offering to resume playback in guild {}: {:?}",
+                guild_id, err
+            );
+        }
+    }
+}
+
+/// Handle a press on a resume offer, rejoining the saved voice channel and
+/// continuing the queue where it stopped, or forgetting it
+pub(crate) async fn handle_resume(
+    ctx: &serenity::Context,
+    data: &Data,
+    press: &ComponentInteraction,
+) -> anyhow::Result<()> {
+    let guild_id = press
+        .guild_id
+        .ok_or(anyhow!("Resuming only works inside a guild"))?;
+    // Resuming follows the rules of `play`, throwing the saved queue away those of `clear`
+    let command = match press.data.custom_id.as_str() {
+        RESUME => "play",
+        DISMISS => "clear",
+        id => return Err(anyhow!("Unknown resume button {}", id)),
+    };
+    let settings = data.settings.get(guild_id).await;
+    let refusal = match check_user(
+        ctx,
+        &settings,
+        guild_id,
+        press.channel_id,
+        press.user.id,
+        command,
+    )
+    .await
+    {
+        Ok(()) => None,
+        Err(Refusal::Blocked) => {
+            press
+                .create_response(ctx, CreateInteractionResponse::Acknowledge)
+                .await?;
+            return Ok(());
+        }
+        Err(Refusal::Reason(reason)) => Some(reason),
+    };
+    // The channel may have been denied since the queue was saved
+    let refusal = match data.queues.offer_channel(guild_id).await {
+        Some(channel_id) if command == "play" && !settings.allows_voice_channel(channel_id) => {
+            refusal.or(Some(format!(
+                "I'm not allowed to join <#{}> any more, I can join: {}",
+                channel_id,
+                allowed_channels(ctx, &settings, guild_id, true)
+            )))
+        }
+        _ => refusal,
+    };
+    if let Some(reason) = refusal {
+        // The offer stays for someone who may take it up
+        let reply = CreateInteractionResponseMessage::new()
+            .content(reason)
+            .ephemeral(true);
+        press
+            .create_response(ctx, CreateInteractionResponse::Message(reply))
+            .await?;
+        return Ok(());
+    }
+    let Some(saved) = data.queues.take_offer(guild_id).await? else {
+        let reply = CreateInteractionResponseMessage::new()
+            .content("There is nothing left to resume")
+            .components(vec![]);
+        press
+            .create_response(ctx, CreateInteractionResponse::UpdateMessage(reply))
+            .await?;
+        return Ok(());
+    };
+    let content = if press.data.custom_id == RESUME {
+        format!("<@{}> resumed playback", press.user.id)
+    } else {
+        format!("<@{}> dismissed the saved queue", press.user.id)
+    };
+    // Joining takes a moment, answer before the interaction expires
+    press
+        .create_response(
+            ctx,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content(content.clone())
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+    if press.data.custom_id != RESUME {
+        return Ok(());
+    }
+    match resume_session(ctx, data, guild_id, saved).await {
+        Ok(0) => {}
+        Ok(left_out) => {
+            let msg = format!(
+                "{}, the queue is full so {} songs were left out",
+                content, left_out
+            );
+            press
+                .edit_response(ctx, EditInteractionResponse::new().content(msg))
+                .await?;
+        }
+        Err(err) => {
+            press
+                .edit_response(
+                    ctx,
+                    EditInteractionResponse::new()
+                        .content(format!("Error resuming playback: {}", err)),
+                )
+                .await?;
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Rejoin and play a saved queue, returning how many songs did not fit in the queue
+async fn resume_session(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: GuildId,
+    saved: SavedSession,
+) -> anyhow::Result<usize> {
+    let manager = songbird::get(ctx).await.ok_or(anyhow!(
+        "Songbird Voice client placed in at initialisation."
+    ))?;
+    let handler_lock = manager.join(guild_id, saved.voice_channel).await?;
+    let session = data.sessions.get(guild_id).await;
+    session
+        .text_channel
+        .lock()
+        .await
+        .replace(saved.text_channel);
+    session.mark_active().await;
+    *session.loop_mode.lock().await = saved.loop_mode;
+
+    let http_client = get_http_client(ctx).await;
+    let mut items: Vec<QueuedItem> = saved
+        .current
+        .into_iter()
+        .chain(saved.queue)
+        .map(|track| track.into_item(http_client.clone()))
+        .collect();
+    let mut handler = handler_lock.lock().await;
+    let playing = handler.queue().current().is_some();
+    // The song that starts right away takes no place in the queue
+    let room = data
+        .settings
+        .get(guild_id)
+        .await
+        .max_queue
+        .saturating_sub(session.queue.lock().await.len())
+        + usize::from(!playing);
+    let left_out = items.len().saturating_sub(room);
+    items.truncate(room);
+    if playing {
+        // Someone started playing in the meantime, the saved songs go after theirs
+        session.queue.lock().await.extend(items);
+        return Ok(left_out);
+    }
+    if items.is_empty() {
+        return Ok(left_out);
+    }
+    let first = items.remove(0);
+    session.queue.lock().await.extend(items);
+    let track = start_playing(ctx, data, guild_id, &mut handler, session, first).await?;
+    match saved.loops {
+        SavedLoop::Infinite => track.enable_loop()?,
+        SavedLoop::Finite(count) => track.loop_for(count)?,
+        SavedLoop::Off => {}
+    }
+    info!("Resumed playback in guild {}", guild_id);
+    Ok(left_out)
+}
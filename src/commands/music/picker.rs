@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use super::funts::format_duration;
+use super::session::QueuedItem;
+use crate::Context;
+use anyhow::anyhow;
+use poise::CreateReply;
+use poise::serenity_prelude as serenity;
+use serenity::{
+    ComponentInteractionCollector, ComponentInteractionDataKind, CreateActionRow,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateSelectMenu,
+    CreateSelectMenuKind, CreateSelectMenuOption,
+};
+
+/// Number of search results offered to pick from
+pub(crate) const PICK_RESULTS: usize = 5;
+/// How long to wait for a pick before playing the first result
+const PICK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Discord cuts select menu labels and descriptions at this length
+const OPTION_MAX_LEN: usize = 100;
+
+/// Whether `query` is free text to search for rather than a link or a prefixed query
+pub(crate) fn is_search(query: &str) -> bool {
+    url::Url::parse(query.trim()).is_err()
+}
+
+fn truncate(text: &str) -> String {
+    if text.chars().count() <= OPTION_MAX_LEN {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(OPTION_MAX_LEN - 1).collect();
+    short.push('…');
+    short
+}
+
+fn result_option(index: usize, item: &QueuedItem) -> CreateSelectMenuOption {
+    let metadata = &item.metadata;
+    let title = metadata.title.as_deref().unwrap_or("Unknown Title");
+    let channel = metadata
+        .channel
+        .as_deref()
+        .or(metadata.artist.as_deref())
+        .unwrap_or("Unknown Channel");
+    let duration = metadata
+        .duration
+        .map(format_duration)
+        .unwrap_or_else(|| "--:--".to_string());
+    CreateSelectMenuOption::new(
+        truncate(&format!("{}. {}", index + 1, title)),
+        index.to_string(),
+    )
+    .description(truncate(&format!("{} • {}", channel, duration)))
+}
+
+/// Let the author pick one of the search results from a select menu.
+/// Nobody picking in time gets the first result.
+pub(crate) async fn pick_search_result(
+    ctx: &Context<'_>,
+    mut results: Vec<QueuedItem>,
+) -> anyhow::Result<QueuedItem> {
+    if results.len() <= 1 {
+        return results.pop().ok_or(anyhow!("No search results found"));
+    }
+    let menu_id = format!("{}pick", ctx.id());
+    let options = results
+        .iter()
+        .enumerate()
+        .map(|(index, item)| result_option(index, item))
+        .collect();
+    let menu = CreateSelectMenu::new(&menu_id, CreateSelectMenuKind::String { options })
+        .placeholder("Pick a song");
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .content("Which one?")
+                .components(vec![CreateActionRow::SelectMenu(menu)]),
+        )
+        .await?;
+
+    let author_id = ctx.author().id;
+    let press = ComponentInteractionCollector::new(ctx)
+        .filter(move |press| press.data.custom_id == menu_id && press.user.id == author_id)
+        .timeout(PICK_TIMEOUT)
+        .await;
+    let picked = match &press {
+        Some(press) => match &press.data.kind {
+            ComponentInteractionDataKind::StringSelect { values } => values
+                .first()
+                .and_then(|value| value.parse::<usize>().ok())
+                .filter(|index| *index < results.len())
+                .unwrap_or(0),
+            _ => 0,
+        },
+        None => 0,
+    };
+    let item = results.swap_remove(picked);
+    let msg = format!(
+        "Picked **{}**",
+        item.metadata.title.as_deref().unwrap_or("Unknown Title")
+    );
+    match press {
+        Some(press) => {
+            press
+                .create_response(
+                    ctx.serenity_context(),
+                    CreateInteractionResponse::UpdateMessage(
+                        CreateInteractionResponseMessage::new()
+                            .content(msg)
+                            .components(vec![]),
+                    ),
+                )
+                .await?;
+        }
+        None => {
+            reply
+                .edit(
+                    *ctx,
+                    CreateReply::default()
+                        .content(format!("{} as nobody picked", msg))
+                        .components(vec![]),
+                )
+                .await?;
+        }
+    }
+    Ok(item)
+}
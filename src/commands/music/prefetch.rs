@@ -0,0 +1,234 @@
+use super::add::get_http_client;
+use super::session::{GuildSession, QueuedItem, TrackInput};
+use crate::config::yt_dlp;
+use anyhow::anyhow;
+use poise::serenity_prelude as serenity;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use serenity::CreateMessage;
+use songbird::input::{AuxMetadata, HttpRequest};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+/// How many of the pending songs are looked up before their turn
+const PREFETCH_AHEAD: usize = 3;
+/// yt-dlp runs at most this many lookups at once, across every guild
+static PREFETCH_PERMITS: Semaphore = Semaphore::const_new(2);
+
+/// The parts of yt-dlp's json output needed to play a song without asking it again
+#[derive(Deserialize, Debug)]
+struct StreamInfo {
+    url: String,
+    protocol: Option<String>,
+    #[serde(default)]
+    http_headers: HashMap<String, String>,
+    filesize: Option<u64>,
+    duration: Option<f64>,
+    thumbnail: Option<String>,
+    channel: Option<String>,
+    uploader: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    track: Option<String>,
+    upload_date: Option<String>,
+}
+
+impl StreamInfo {
+    /// Fill in what a flat playlist entry left out, keeping the `source_url`
+    /// the song was queued with
+    fn complete(&self, metadata: &mut AuxMetadata) {
+        metadata.duration = metadata
+            .duration
+            .or(self.duration.map(Duration::from_secs_f64));
+        metadata.thumbnail = metadata.thumbnail.take().or(self.thumbnail.clone());
+        metadata.artist = metadata
+            .artist
+            .take()
+            .or(self.artist.clone())
+            .or(self.uploader.clone());
+        metadata.channel = metadata.channel.take().or(self.channel.clone());
+        metadata.album = metadata.album.take().or(self.album.clone());
+        metadata.track = metadata.track.take().or(self.track.clone());
+        metadata.date = metadata.date.take().or(self.upload_date.clone());
+    }
+
+    /// Request for the stream itself, HLS streams are left to yt-dlp on play
+    fn stream_request(&self, http_client: &reqwest::Client) -> Option<HttpRequest> {
+        if self.protocol.as_deref() == Some("m3u8_native") {
+            return None;
+        }
+        let headers: HeaderMap = self
+            .http_headers
+            .iter()
+            .filter_map(|(key, value)| {
+                Some((
+                    HeaderName::from_bytes(key.as_bytes()).ok()?,
+                    HeaderValue::from_str(value).ok()?,
+                ))
+            })
+            .collect();
+        Some(HttpRequest {
+            client: http_client.clone(),
+            request: self.url.clone(),
+            headers,
+            content_length: self.filesize,
+        })
+    }
+}
+
+/// Ask yt-dlp for the stream of a single song, the same way songbird does on play
+async fn lookup(url: &str) -> anyhow::Result<StreamInfo> {
+    let output = Command::new(yt_dlp())
+        .args([
+            "-j",
+            url,
+            "-f",
+            "ba[abr>0][vcodec=none]/best",
+            "--no-playlist",
+        ])
+        .output()
+        .await
+        .map_err(|err| {
+            if err.kind() == ErrorKind::NotFound {
+                anyhow!("could not find executable '{}' on path", yt_dlp())
+            } else {
+                err.into()
+            }
+        })?;
+    if !output.status.success() {
+        // The last line holds the reason, like `ERROR: Video unavailable`
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("yt-dlp failed");
+        return Err(anyhow!("{}", reason.trim()));
+    }
+    let line = output
+        .stdout
+        .split(|&b| b == b'\n')
+        .find(|line| !line.is_empty())
+        .ok_or(anyhow!("yt-dlp found nothing for {}", url))?;
+    Ok(serde_json::from_slice(line)?)
+}
+
+/// Source url of a pending YouTube song that still has to be looked up
+fn needs_prefetch(item: &QueuedItem) -> Option<&str> {
+    match &item.input {
+        TrackInput::Youtube(_) if !item.live => item.metadata.source_url.as_deref(),
+        _ => None,
+    }
+}
+
+/// Look up the stream links of the next few pending songs in the background,
+/// so the next song starts without waiting on yt-dlp. Songs that cannot be
+/// played are dropped from the queue with a notice before their turn comes.
+pub(crate) fn prefetch_upcoming(
+    session: Arc<GuildSession>,
+    http: Arc<serenity::Http>,
+    http_client: reqwest::Client,
+) {
+    tokio::spawn(async move {
+        let urls: Vec<String> = {
+            let queue = session.queue.lock().await;
+            let mut prefetching = session.prefetching.lock().await;
+            queue
+                .iter()
+                .take(PREFETCH_AHEAD)
+                .filter_map(needs_prefetch)
+                .filter(|url| prefetching.insert(url.to_string()))
+                .map(str::to_string)
+                .collect()
+        };
+        for url in urls {
+            let session = session.clone();
+            let http = http.clone();
+            let http_client = http_client.clone();
+            tokio::spawn(async move {
+                prefetch(&session, &http, &http_client, &url).await;
+                session.prefetching.lock().await.remove(&url);
+            });
+        }
+    });
+}
+
+/// Prefetch for the queue of a guild after songs were added or reordered
+pub(crate) async fn prefetch_queue(ctx: &serenity::Context, session: Arc<GuildSession>) {
+    prefetch_upcoming(session, ctx.http.clone(), get_http_client(ctx).await);
+}
+
+async fn prefetch(
+    session: &GuildSession,
+    http: &serenity::Http,
+    http_client: &reqwest::Client,
+    url: &str,
+) {
+    let Ok(_permit) = PREFETCH_PERMITS.acquire().await else {
+        return;
+    };
+    // The song may have been played, removed or cleared while waiting its turn
+    let still_pending = session
+        .queue
+        .lock()
+        .await
+        .iter()
+        .any(|item| needs_prefetch(item) == Some(url));
+    if !still_pending {
+        return;
+    }
+    info!("Prefetching {}", url);
+    let result = lookup(url).await;
+    let mut queue = session.queue.lock().await;
+    match result {
+        Ok(info) => {
+            for item in queue.iter_mut() {
+                if needs_prefetch(item) != Some(url) {
+                    continue;
+                }
+                info.complete(&mut item.metadata);
+                let TrackInput::Youtube(fallback) = &item.input else {
+                    continue;
+                };
+                if let Some(stream) = info.stream_request(http_client) {
+                    item.input = TrackInput::Prefetched {
+                        stream,
+                        resolved_at: Instant::now(),
+                        fallback: fallback.clone(),
+                    };
+                }
+            }
+        }
+        Err(err) => {
+            warn!("Error prefetching {}: {:?}", url, err);
+            let mut dropped = Vec::new();
+            queue.retain(|item| {
+                let broken = needs_prefetch(item) == Some(url);
+                if broken {
+                    dropped.push(item.metadata.title.clone());
+                }
+                !broken
+            });
+            drop(queue);
+            let Some(channel) = *session.text_channel.lock().await else {
+                return;
+            };
+            for title in dropped {
+                let msg = format!(
+                    "Dropped **{}** from the queue, it can't be played: {}",
+                    title.as_deref().unwrap_or(url),
+                    err
+                );
+                channel
+                    .send_message(http, CreateMessage::new().content(msg))
+                    .await
+                    .ok();
+            }
+        }
+    }
+}
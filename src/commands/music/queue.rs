@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use super::common::{get_guild_session, join_n_get_voice_channel_handler};
+use super::funts::format_duration;
+use super::prefetch::prefetch_queue;
+use super::session::{LoopMode, QueuedItem};
+use crate::{Context, Error};
+use anyhow::anyhow;
+use poise::serenity_prelude as serenity;
+use poise::{self, CreateReply};
+use serenity::{
+    ComponentInteractionCollector, CreateActionRow, CreateButton, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+
+/// Number of queued songs listed on one page
+const PAGE_SIZE: usize = 10;
+/// How long the page buttons keep working after the last press
+const PAGE_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[poise::command(slash_command, prefix_command, guild_only)]
+/// Show the songs waiting in the queue
+pub async fn queue(
+    ctx: Context<'_>,
+    #[description = "Page to start on"] page: Option<usize>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let ctx_id = ctx.id();
+    let prev_button_id = format!("{}prev", ctx_id);
+    let next_button_id = format!("{}next", ctx_id);
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(&prev_button_id).emoji('◀'),
+        CreateButton::new(&next_button_id).emoji('▶'),
+    ]);
+
+    let mut current_page = page.unwrap_or(1).saturating_sub(1);
+    let (embed, page_count) = queue_page(&ctx, current_page).await?;
+    current_page = current_page.min(page_count - 1);
+    let reply = ctx
+        .send(
+            CreateReply::default()
+                .embed(embed)
+                .components(vec![buttons]),
+        )
+        .await?;
+
+    while let Some(press) = ComponentInteractionCollector::new(ctx)
+        .filter(move |press| press.data.custom_id.starts_with(&ctx_id.to_string()))
+        .timeout(PAGE_TIMEOUT)
+        .await
+    {
+        // The queue may have changed since the last page was shown
+        let (_, page_count) = queue_page(&ctx, 0).await?;
+        if press.data.custom_id == next_button_id {
+            current_page = (current_page + 1) % page_count;
+        } else if press.data.custom_id == prev_button_id {
+            current_page = current_page.checked_sub(1).unwrap_or(page_count - 1);
+        } else {
+            continue;
+        }
+        let (embed, _) = queue_page(&ctx, current_page).await?;
+        press
+            .create_response(
+                ctx.serenity_context(),
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new().embed(embed),
+                ),
+            )
+            .await?;
+    }
+
+    // Navigation timed out, drop the buttons so nobody presses dead ones
+    reply
+        .edit(ctx, CreateReply::default().components(vec![]))
+        .await?;
+    Ok(())
+}
+
+/// Build one page of the queue listing for the guild of the context,
+/// returning it with the total number of pages
+async fn queue_page(ctx: &Context<'_>, page: usize) -> anyhow::Result<(CreateEmbed, usize)> {
+    let session = get_guild_session(ctx).await?;
+    let cur_song = session.cur_song.lock().await.clone();
+    let cur_position = current_track_position(ctx).await;
+    let queue = session.queue.lock().await;
+    Ok(build_queue_page(
+        cur_song.as_ref(),
+        cur_position,
+        &queue,
+        page,
+    ))
+}
+
+/// Position of the track currently playing in the guild, without joining any channel
+async fn current_track_position(ctx: &Context<'_>) -> Option<Duration> {
+    let manager = songbird::get(ctx.serenity_context()).await?;
+    let handler_lock = manager.get(ctx.guild_id()?)?;
+    let cur_track = handler_lock.lock().await.queue().current()?;
+    cur_track.get_info().await.ok().map(|state| state.position)
+}
+
+pub(crate) fn describe_item(item: &QueuedItem) -> String {
+    let title = item.metadata.title.as_deref().unwrap_or("Unknown Title");
+    let title = match item.metadata.source_url.as_deref() {
+        // Local songs have a `local:` query instead of a link
+        Some(url) if url.starts_with("http") => format!("[{}]({})", title, url),
+        _ => title.to_string(),
+    };
+    let duration = match item.metadata.duration {
+        _ if item.live => "LIVE".to_string(),
+        Some(duration) => format_duration(duration),
+        None => "--:--".to_string(),
+    };
+    format!("{} `{}` • <@{}>", title, duration, item.requester)
+}
+
+fn build_queue_page(
+    cur_song: Option<&QueuedItem>,
+    cur_position: Option<Duration>,
+    queue: &VecDeque<QueuedItem>,
+    page: usize,
+) -> (CreateEmbed, usize) {
+    let page_count = queue.len().div_ceil(PAGE_SIZE).max(1);
+    let page = page.min(page_count - 1);
+
+    let mut description = String::new();
+    let mut remaining = Duration::ZERO;
+    if let Some(cur_song) = cur_song {
+        description.push_str(&format!("**Now playing:** {}\n\n", describe_item(cur_song)));
+        if let Some(duration) = cur_song.metadata.duration {
+            remaining += duration.saturating_sub(cur_position.unwrap_or_default());
+        }
+    }
+    remaining += queue
+        .iter()
+        .filter_map(|item| item.metadata.duration)
+        .sum::<Duration>();
+
+    if queue.is_empty() {
+        description.push_str("No songs waiting in the queue.");
+    }
+    for (index, item) in queue
+        .iter()
+        .enumerate()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+    {
+        // The song playing right now is not numbered, the queue starts at 1
+        description.push_str(&format!("**{}.** {}\n", index + 1, describe_item(item)));
+    }
+
+    let embed = CreateEmbed::new()
+        .colour(0x1DB954)
+        .title("Queue")
+        .description(description)
+        .footer(CreateEmbedFooter::new(format!(
+            "Page {}/{} • {} songs • {} remaining",
+            page + 1,
+            page_count,
+            queue.len(),
+            format_duration(remaining)
+        )));
+    (embed, page_count)
+}
+
+/// Parse a queue position like `3` or a range like `3-7`, both ends inclusive
+/// and counted the same way as the `queue` listing
+fn parse_positions(positions: &str, queue_len: usize) -> anyhow::Result<RangeInclusive<usize>> {
+    let parse = |pos: &str| {
+        pos.trim()
+            .parse::<usize>()
+            .map_err(|_| anyhow!("`{}` is not a queue position", pos.trim()))
+    };
+    let range = match positions.split_once('-') {
+        Some((start, end)) => parse(start)?..=parse(end)?,
+        None => {
+            let pos = parse(positions)?;
+            pos..=pos
+        }
+    };
+    check_position(*range.start(), queue_len)?;
+    check_position(*range.end(), queue_len)?;
+    if range.start() > range.end() {
+        return Err(anyhow!("The range {} is backwards", positions));
+    }
+    Ok(range)
+}
+
+fn check_position(pos: usize, queue_len: usize) -> anyhow::Result<()> {
+    if queue_len == 0 {
+        return Err(anyhow!("The queue is empty"));
+    }
+    if pos == 0 || pos > queue_len {
+        return Err(anyhow!(
+            "Position {} is out of range, the queue has songs 1 to {}",
+            pos,
+            queue_len
+        ));
+    }
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, guild_only)]
+/// Remove a song or a range of songs (like `3-7`) from the queue
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "Position or range of positions shown by the queue command"] positions: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let session = get_guild_session(&ctx).await?;
+    let removed: Vec<QueuedItem> = {
+        let mut queue = session.queue.lock().await;
+        let range = parse_positions(&positions, queue.len())?;
+        queue.drain(range.start() - 1..*range.end()).collect()
+    };
+    prefetch_queue(ctx.serenity_context(), session).await;
+    let msg = match removed.as_slice() {
+        [item] => format!(
+            "Removed {} from the queue",
+            item.metadata.title.as_deref().unwrap_or("Unknown Title")
+        ),
+        items => format!("Removed {} songs from the queue", items.len()),
+    };
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, guild_only, rename = "move")]
+/// Move a song to another position in the queue
+pub async fn move_song(
+    ctx: Context<'_>,
+    #[description = "Current position of the song"] from: usize,
+    #[description = "New position of the song"] to: usize,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let session = get_guild_session(&ctx).await?;
+    let title = {
+        let mut queue = session.queue.lock().await;
+        check_position(from, queue.len())?;
+        check_position(to, queue.len())?;
+        let item = queue
+            .remove(from - 1)
+            .ok_or(anyhow!("Error getting song at position {}", from))?;
+        let title = item.metadata.title.clone();
+        queue.insert(to - 1, item);
+        title
+    };
+    prefetch_queue(ctx.serenity_context(), session).await;
+    ctx.say(format!(
+        "Moved {} to position {}",
+        title.as_deref().unwrap_or("Unknown Title"),
+        to
+    ))
+    .await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, guild_only)]
+/// Skip straight to a song in the queue, the songs before it are dropped unless the queue loops
+pub async fn skipto(
+    ctx: Context<'_>,
+    #[description = "Position of the song to play next"] position: usize,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
+    let session = get_guild_session(&ctx).await?;
+    let looping = *session.loop_mode.lock().await == LoopMode::Queue;
+    {
+        let mut queue = session.queue.lock().await;
+        check_position(position, queue.len())?;
+        let skipped: Vec<QueuedItem> = queue.drain(..position - 1).collect();
+        if looping {
+            // The looped queue keeps every song in order: the current song goes back
+            // ahead of the skipped ones, so the song end notifier must not add it again
+            if let Some(current) = session.cur_song.lock().await.take() {
+                queue.push_back(current);
+            }
+            queue.extend(skipped);
+        }
+    }
+    // Ending the current track hands the front of the queue to songbird
+    handler_lock.lock().await.queue().skip()?;
+    ctx.say(format!("Skipped to song {}", position)).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, guild_only)]
+/// Remove every song waiting in the queue, the current song keeps playing
+pub async fn clear(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer().await?;
+    let session = get_guild_session(&ctx).await?;
+    let cleared = {
+        let mut queue = session.queue.lock().await;
+        let cleared = queue.len();
+        queue.clear();
+        cleared
+    };
+    ctx.say(format!("Cleared {} songs from the queue", cleared))
+        .await?;
+    Ok(())
+}
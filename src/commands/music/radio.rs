@@ -0,0 +1,290 @@
+use super::add::{get_http_client, play_sources};
+use super::common::join_n_get_voice_channel_handler;
+use super::session::{GuildSession, QueuedItem, TrackInput};
+use crate::{Context, Error};
+use anyhow::anyhow;
+use poise::serenity_prelude::UserId;
+use songbird::input::{AuxMetadata, HttpRequest};
+use songbird::tracks::PlayMode;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tracing::{info, warn};
+use url::Url;
+
+/// Most stations are fine with this many presets, and the list stays readable
+const MAX_STATIONS: usize = 25;
+
+/// Build the input for an endless stream, HLS playlists need their own reader
+pub(crate) fn stream_input(http_client: reqwest::Client, url: String) -> TrackInput {
+    let is_hls =
+        Url::parse(&url).is_ok_and(|parsed| parsed.path().to_ascii_lowercase().ends_with(".m3u8"));
+    if is_hls {
+        TrackInput::Hls(http_client, url)
+    } else {
+        TrackInput::Http(HttpRequest::new(http_client, url))
+    }
+}
+
+fn is_http_link(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|parsed| parsed.scheme() == "http" || parsed.scheme() == "https")
+}
+
+fn station_item(
+    http_client: reqwest::Client,
+    url: String,
+    name: Option<String>,
+    requester: UserId,
+) -> anyhow::Result<QueuedItem> {
+    if !is_http_link(&url) {
+        return Err(anyhow!("`{}` is not a saved station or an http link", url));
+    }
+    let metadata = AuxMetadata {
+        title: name.or_else(|| Url::parse(&url).ok()?.host_str().map(str::to_string)),
+        artist: Some("Radio".to_string()),
+        source_url: Some(url.clone()),
+        ..Default::default()
+    };
+    Ok(QueuedItem {
+        input: stream_input(http_client, url),
+        metadata,
+        requester,
+        start: None,
+        live: true,
+    })
+}
+
+#[poise::command(slash_command, prefix_command, guild_only)]
+/// Play an internet radio stream, by link or by the name of a saved station
+pub async fn radio(
+    ctx: Context<'_>,
+    #[description = "Icecast, Shoutcast or HLS link, or a saved station"] station: String,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let stations = ctx.data().settings.get(guild_id).await.radio_stations;
+    let (url, name) = match stations.get(&station.to_lowercase()) {
+        Some(url) => (url.clone(), Some(station.to_lowercase())),
+        None => (station, None),
+    };
+    let handler_lock = join_n_get_voice_channel_handler(&ctx).await?;
+    let http_client = get_http_client(ctx.serenity_context()).await;
+    let item = station_item(http_client, url, name, ctx.author().id)?;
+    let track_handle = play_sources(ctx, handler_lock, VecDeque::from([item]), false).await?;
+    ctx.reply("tuned in").await?;
+    if track_handle.get_info().await?.playing != PlayMode::Play {
+        track_handle.play()?;
+    }
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    subcommands("station_save", "station_delete", "station_list"),
+    subcommand_required
+)]
+/// Manage the radio stations saved for this server
+pub async fn stations(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "save",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Save a station so `radio <name>` plays it
+pub async fn station_save(
+    ctx: Context<'_>,
+    #[description = "Short name, like lofi"] name: String,
+    #[description = "Stream link"] url: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(anyhow!("Station names are a single word").into());
+    }
+    if !is_http_link(&url) {
+        return Err(anyhow!("`{}` is not an http or https link", url).into());
+    }
+    let current = ctx.data().settings.get(guild_id).await.radio_stations;
+    if !current.contains_key(&name) && current.len() >= MAX_STATIONS {
+        return Err(anyhow!("This server already has {} stations saved", MAX_STATIONS).into());
+    }
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            settings.radio_stations.insert(name.clone(), url);
+        })
+        .await?;
+    ctx.say(format!("Saved station `{}`", name)).await?;
+    Ok(())
+}
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    rename = "delete",
+    required_permissions = "MANAGE_GUILD"
+)]
+/// Delete a saved station
+pub async fn station_delete(
+    ctx: Context<'_>,
+    #[description = "Name of the station"] name: String,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let name = name.trim().to_lowercase();
+    let mut removed = false;
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            removed = settings.radio_stations.remove(&name).is_some();
+        })
+        .await?;
+    if !removed {
+        return Err(anyhow!("There is no station called `{}`", name).into());
+    }
+    ctx.say(format!("Deleted station `{}`", name)).await?;
+    Ok(())
+}
+
+#[poise::command(slash_command, prefix_command, guild_only, rename = "list")]
+/// List the saved stations
+pub async fn station_list(ctx: Context<'_>) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    let stations = ctx.data().settings.get(guild_id).await.radio_stations;
+    let msg = if stations.is_empty() {
+        "No stations saved yet, add one with `stations save`".to_string()
+    } else {
+        stations
+            .iter()
+            .map(|(name, url)| format!("`{}` <{}>", name, url))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+/// Follow the `StreamTitle` of an Icecast or Shoutcast stream while it plays,
+/// showing the song the station is on as the title in the now playing embed
+pub(crate) fn watch_stream_title(session: Arc<GuildSession>, item: &QueuedItem) {
+    let TrackInput::Http(request) = &item.input else {
+        return;
+    };
+    if !item.live {
+        return;
+    }
+    let client = request.client.clone();
+    let url = request.request.clone();
+    let generation = session.current_generation();
+    tokio::spawn(async move {
+        if let Err(err) = read_stream_titles(&client, &url, &session, generation).await {
+            warn!("Error reading stream titles of {}: {:?}", url, err);
+        }
+    });
+}
+
+/// Whether the stream at `url` is still the song playing in this generation
+async fn still_playing(session: &GuildSession, url: &str, generation: usize) -> bool {
+    session.current_generation() == generation
+        && session
+            .cur_song
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|item| item.metadata.source_url.as_deref() == Some(url))
+}
+
+/// Open a second connection asking for ICY metadata, which the server mixes
+/// into the audio every `icy-metaint` bytes. The audio itself is thrown away.
+async fn read_stream_titles(
+    client: &reqwest::Client,
+    url: &str,
+    session: &GuildSession,
+    generation: usize,
+) -> anyhow::Result<()> {
+    let mut response = client
+        .get(url)
+        .header("Icy-MetaData", "1")
+        .send()
+        .await?
+        .error_for_status()?;
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let station_name = header("icy-name").filter(|name| !name.trim().is_empty());
+    let Some(metaint) = header("icy-metaint").and_then(|value| value.parse::<usize>().ok()) else {
+        info!("{} sends no stream titles", url);
+        return Ok(());
+    };
+    if let Some(station_name) = station_name {
+        if !still_playing(session, url, generation).await {
+            return Ok(());
+        }
+        if let Some(item) = session.cur_song.lock().await.as_mut() {
+            item.metadata.artist = Some(station_name);
+        }
+    }
+
+    let mut audio_left = metaint;
+    let mut meta_left: Option<usize> = None;
+    let mut meta = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        let mut data = &chunk[..];
+        while !data.is_empty() {
+            if audio_left > 0 {
+                let skip = audio_left.min(data.len());
+                audio_left -= skip;
+                data = &data[skip..];
+                continue;
+            }
+            // A metadata block starts with its length in units of 16 bytes
+            let left = match meta_left {
+                Some(left) => left,
+                None => {
+                    let len = data[0] as usize * 16;
+                    data = &data[1..];
+                    meta.clear();
+                    len
+                }
+            };
+            let take = left.min(data.len());
+            meta.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            meta_left = Some(left - take);
+            if meta_left != Some(0) {
+                continue;
+            }
+            meta_left = None;
+            audio_left = metaint;
+            if !still_playing(session, url, generation).await {
+                return Ok(());
+            }
+            if let Some(title) = stream_title(&meta)
+                && let Some(item) = session.cur_song.lock().await.as_mut()
+            {
+                item.metadata.title = Some(title);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pull the title out of a block like `StreamTitle='Artist - Song';StreamUrl='';`
+fn stream_title(meta: &[u8]) -> Option<String> {
+    let meta = String::from_utf8_lossy(meta);
+    let start = meta.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &meta[start..];
+    let end = rest.find("';").unwrap_or(rest.trim_end_matches('\0').len());
+    let title = rest[..end].trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
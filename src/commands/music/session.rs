@@ -0,0 +1,201 @@
+use poise::serenity_prelude as serenity;
+use serde::{Deserialize, Serialize};
+use serenity::{ChannelId, GuildId, Timestamp, UserId};
+use songbird::input::{AuxMetadata, File, HlsRequest, HttpRequest, Input, YoutubeDl};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Where the audio of a queued song comes from
+#[derive(Clone, Debug)]
+pub enum TrackInput {
+    Youtube(YoutubeDl<'static>),
+    /// A direct link to an audio file
+    Http(HttpRequest),
+    /// A file from the local library
+    File(PathBuf),
+    /// An HLS playlist, songbird's request for it cannot be cloned so it is built on play
+    Hls(reqwest::Client, String),
+    /// A YouTube song whose stream link was looked up ahead of time. Stream
+    /// links expire, an old one is looked up again through `fallback`.
+    Prefetched {
+        stream: HttpRequest,
+        resolved_at: Instant,
+        fallback: YoutubeDl<'static>,
+    },
+}
+
+/// How long a prefetched stream link is trusted, YouTube links last a few hours
+pub const STREAM_LINK_TTL: Duration = Duration::from_secs(60 * 60);
+
+impl From<TrackInput> for Input {
+    fn from(input: TrackInput) -> Self {
+        match input {
+            TrackInput::Youtube(ytdl) => ytdl.into(),
+            TrackInput::Http(request) => request.into(),
+            TrackInput::File(path) => File::new(path).into(),
+            TrackInput::Hls(client, url) => HlsRequest::new(client, url).into(),
+            TrackInput::Prefetched {
+                stream,
+                resolved_at,
+                fallback,
+            } => {
+                if resolved_at.elapsed() < STREAM_LINK_TTL {
+                    stream.into()
+                } else {
+                    fallback.into()
+                }
+            }
+        }
+    }
+}
+
+/// A song in the queue along with the metadata resolved when it was enqueued,
+/// so listing the queue never has to call yt-dlp again
+#[derive(Clone, Debug)]
+pub struct QueuedItem {
+    pub input: TrackInput,
+    pub metadata: AuxMetadata,
+    pub requester: UserId,
+    /// Where playback should begin, e.g. from a `t=` link
+    pub start: Option<Duration>,
+    /// Live streams have no end, the embed shows a badge instead of a progress bar
+    pub live: bool,
+}
+
+/// What happens once the current song ends
+#[derive(
+    Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+pub enum LoopMode {
+    /// Move on to the next song
+    #[default]
+    #[name = "off"]
+    Off,
+    /// Play the current song over and over
+    #[name = "track"]
+    Track,
+    /// Put every finished song back at the end of the queue
+    #[name = "queue"]
+    Queue,
+}
+
+impl LoopMode {
+    /// The mode a loop button press switches to
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Track,
+            Self::Track => Self::Queue,
+            Self::Queue => Self::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Track => "🔂 Track",
+            Self::Queue => "🔁 Queue",
+        }
+    }
+}
+
+/// Most songs kept in the play history of a guild
+pub const HISTORY_LIMIT: usize = 50;
+
+/// A song that started playing, for the play history
+#[derive(Clone, Debug)]
+pub struct PlayedTrack {
+    pub item: QueuedItem,
+    pub played_at: Timestamp,
+    /// For songs played again by `previous`, how many songs back it went
+    pub steps_back: Option<usize>,
+}
+
+/// Playback state belonging to a single guild.
+/// Everything that used to live globally in `Data` is kept here so that
+/// guilds sharing one bot instance never see each other's songs.
+#[derive(Default)]
+pub struct GuildSession {
+    /// Songs waiting to be handed over to the songbird queue, next song first
+    pub queue: Mutex<VecDeque<QueuedItem>>,
+    pub now_playing_msg: Mutex<Option<serenity::Message>>,
+    pub cur_song: Mutex<Option<QueuedItem>>,
+    /// Bumped whenever playback is replaced, so the end events of stopped
+    /// tracks do not pull songs from the new queue. This keeps the songbird
+    /// queue down to the current track and the positions in `queue` exact.
+    pub generation: AtomicUsize,
+    /// Text channel music commands were last used in, for messages from the bot itself
+    pub text_channel: Mutex<Option<ChannelId>>,
+    /// Last time something was playing or a command joined the voice channel
+    pub last_active: Mutex<Option<Instant>>,
+    /// Pending leave after everyone else left the voice channel
+    pub alone_timer: Mutex<Option<JoinHandle<()>>>,
+    /// Source urls of the pending songs being looked up in the background
+    pub prefetching: Mutex<HashSet<String>>,
+    /// Songs played so far, the most recent first and the current song included
+    pub history: Mutex<VecDeque<PlayedTrack>>,
+    /// Steps back `previous` went for the song about to start
+    pub rewind: Mutex<Option<usize>>,
+    pub loop_mode: Mutex<LoopMode>,
+    /// Listeners who voted to skip the current song
+    pub skip_votes: Mutex<HashSet<UserId>>,
+}
+
+impl GuildSession {
+    /// Start a new generation, returning its number
+    pub fn next_generation(&self) -> usize {
+        self.generation.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub fn current_generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Make `item` the current song and add it to the play history
+    pub async fn set_current(&self, item: QueuedItem) {
+        {
+            let steps_back = self.rewind.lock().await.take();
+            let mut history = self.history.lock().await;
+            history.push_front(PlayedTrack {
+                item: item.clone(),
+                played_at: Timestamp::now(),
+                steps_back,
+            });
+            history.truncate(HISTORY_LIMIT);
+        }
+        self.cur_song.lock().await.replace(item);
+        self.skip_votes.lock().await.clear();
+    }
+
+    /// Reset the idle timer
+    pub async fn mark_active(&self) {
+        self.last_active.lock().await.replace(Instant::now());
+    }
+}
+
+/// Registry of all guild sessions, created lazily on first use
+#[derive(Default)]
+pub struct Sessions {
+    inner: Mutex<HashMap<GuildId, Arc<GuildSession>>>,
+}
+
+impl Sessions {
+    /// Get the session for a guild, creating an empty one if it does not exist yet
+    pub async fn get(&self, guild_id: GuildId) -> Arc<GuildSession> {
+        self.inner.lock().await.entry(guild_id).or_default().clone()
+    }
+
+    /// Every session created so far
+    pub async fn all(&self) -> Vec<(GuildId, Arc<GuildSession>)> {
+        self.inner
+            .lock()
+            .await
+            .iter()
+            .map(|(guild_id, session)| (*guild_id, session.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,118 @@
+use super::session::QueuedItem;
+use crate::settings::GuildSettings;
+use anyhow::anyhow;
+use poise::serenity_prelude as serenity;
+use serenity::UserId;
+
+/// Who asked for the songs and with which guild settings
+pub struct ResolveContext {
+    pub http_client: reqwest::Client,
+    pub requester: UserId,
+    pub settings: GuildSettings,
+    pub playlist: PlaylistOptions,
+}
+
+/// Which part of a playlist to queue, positions start at 1
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlaylistOptions {
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+    pub limit: Option<usize>,
+    pub reverse: bool,
+    pub shuffle: bool,
+}
+
+impl PlaylistOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.start == Some(0) || self.end == Some(0) {
+            return Err(anyhow!("Playlist positions start at 1"));
+        }
+        if let (Some(start), Some(end)) = (self.start, self.end)
+            && start > end
+        {
+            return Err(anyhow!(
+                "The playlist start {} is after its end {}",
+                start,
+                end
+            ));
+        }
+        if self.limit == Some(0) {
+            return Err(anyhow!("The limit has to be at least 1"));
+        }
+        Ok(())
+    }
+
+    /// Item selection for yt-dlp's `--playlist-items`, like `3:12`, taking at
+    /// most `max` items whatever the options ask for
+    pub fn playlist_items(&self, max: usize) -> String {
+        let start = self.start.unwrap_or(1);
+        let limit = self.limit.unwrap_or(max).min(max);
+        let last = start.saturating_add(limit).saturating_sub(1);
+        let end = self.end.map_or(last, |end| end.min(last));
+        format!("{}:{}", start, end)
+    }
+}
+
+/// A place songs can come from. Resolvers are asked in order, the first one
+/// that can handle a url turns it into queued songs.
+#[serenity::async_trait]
+pub trait SourceResolver: Send + Sync {
+    /// Whether `url` (or a plain search) belongs to this source
+    fn can_handle(&self, url: &str) -> bool;
+
+    /// Turn `url` into the songs to queue, in order
+    async fn resolve(&self, url: &str, ctx: &ResolveContext) -> anyhow::Result<Vec<QueuedItem>>;
+
+    /// Songs matching a free text query. The `source_url` of every result plays
+    /// it again when given to `resolve`. Sources that cannot search find nothing.
+    async fn search(
+        &self,
+        _query: &str,
+        _ctx: &ResolveContext,
+        _limit: usize,
+    ) -> anyhow::Result<Vec<QueuedItem>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Every source the bot can play from, most specific first
+pub struct ResolverChain {
+    resolvers: Vec<Box<dyn SourceResolver>>,
+}
+
+impl ResolverChain {
+    pub fn new(resolvers: Vec<Box<dyn SourceResolver>>) -> Self {
+        Self { resolvers }
+    }
+
+    /// Resolve `url` with the first source that can handle it
+    pub async fn resolve(
+        &self,
+        url: &str,
+        ctx: &ResolveContext,
+    ) -> anyhow::Result<Vec<QueuedItem>> {
+        let resolver = self
+            .resolvers
+            .iter()
+            .find(|resolver| resolver.can_handle(url))
+            .ok_or(anyhow!("I don't know how to play {}", url))?;
+        let items = resolver.resolve(url, ctx).await?;
+        if items.is_empty() {
+            return Err(anyhow!("Nothing to play was found for {}", url));
+        }
+        Ok(items)
+    }
+
+    /// Search every source, keeping the order of the chain. A source that fails
+    /// to search is skipped so the others still show up.
+    pub async fn search(&self, query: &str, ctx: &ResolveContext, limit: usize) -> Vec<QueuedItem> {
+        let mut found = Vec::new();
+        for resolver in &self.resolvers {
+            match resolver.search(query, ctx, limit).await {
+                Ok(items) => found.extend(items),
+                Err(err) => tracing::info!("Error searching for {}: {:?}", query, err),
+            }
+        }
+        found
+    }
+}
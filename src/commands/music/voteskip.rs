@@ -0,0 +1,107 @@
+use super::autoleave::listeners;
+use crate::commands::config::is_dj;
+use crate::{Context, Data, Error};
+use anyhow::anyhow;
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, GuildId, UserId};
+use songbird::Call;
+
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+/// Make skipping a song take votes from the people listening
+pub async fn voteskip(
+    ctx: Context<'_>,
+    #[description = "Skip by vote instead of on the first `next`"] enabled: Option<bool>,
+    #[description = "Percent of the listeners who have to vote"] percent: Option<u8>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().ok_or(anyhow!("unable to find guild id"))?;
+    if let Some(percent) = percent
+        && !(1..=100).contains(&percent)
+    {
+        return Err(anyhow!("The percent has to be between 1 and 100").into());
+    }
+    let settings = ctx
+        .data()
+        .settings
+        .update(guild_id, |settings| {
+            if let Some(enabled) = enabled {
+                settings.vote_skip = enabled;
+            }
+            if let Some(percent) = percent {
+                settings.skip_vote_percent = percent;
+            }
+        })
+        .await?;
+    let msg = if settings.vote_skip {
+        format!(
+            "Skipping takes votes from {}% of the listeners, the requester and DJs skip right away",
+            settings.skip_vote_percent
+        )
+    } else {
+        "Anyone can skip a song".to_string()
+    };
+    ctx.say(msg).await?;
+    Ok(())
+}
+
+/// Skip the current song for `user_id`, or count their vote when the guild
+/// skips by vote, returning how it went. Only people listening in the bot's
+/// voice channel may vote. `text_channel` is where the skip was asked for.
+pub(crate) async fn request_skip(
+    ctx: &serenity::Context,
+    data: &Data,
+    guild_id: GuildId,
+    text_channel: ChannelId,
+    handler: &Call,
+    user_id: UserId,
+) -> anyhow::Result<String> {
+    let settings = data.settings.get(guild_id).await;
+    let session = data.sessions.get(guild_id).await;
+    let requester = session
+        .cur_song
+        .lock()
+        .await
+        .as_ref()
+        .map(|item| item.requester);
+    if !settings.vote_skip
+        || requester == Some(user_id)
+        || is_dj(ctx, &settings, guild_id, text_channel, user_id).await
+    {
+        handler.queue().skip()?;
+        return Ok("song skipped".to_string());
+    }
+
+    let channel_id = handler
+        .current_channel()
+        .map(|channel| ChannelId::new(channel.0.get()))
+        .ok_or(anyhow!("I'm not in a voice channel"))?;
+    let listeners = listeners(ctx, guild_id, channel_id);
+    if !listeners.contains(&user_id) {
+        return Err(anyhow!(
+            "Only people listening in my voice channel can vote to skip"
+        ));
+    }
+    let needed = (listeners.len() * settings.skip_vote_percent as usize)
+        .div_ceil(100)
+        .max(1);
+    let votes = {
+        let mut votes = session.skip_votes.lock().await;
+        votes.insert(user_id);
+        // Votes of people who left no longer count
+        votes.retain(|voter| listeners.contains(voter));
+        votes.len()
+    };
+    if votes < needed {
+        return Ok(format!(
+            "<@{}> voted to skip ({}/{})",
+            user_id, votes, needed
+        ));
+    }
+    handler.queue().skip()?;
+    Ok(format!("Vote passed ({}/{}), song skipped", votes, needed))
+}
@@ -0,0 +1,204 @@
+use anyhow::{Context, anyhow};
+use clap::Parser;
+use serde::Deserialize;
+use std::env;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::level_filters::LevelFilter;
+
+/// Config file read when no other one is given
+const DEFAULT_CONFIG_FILE: &str = "yoitsus.toml";
+/// yt-dlp executable, set once at startup
+static YT_DLP: OnceLock<&'static str> = OnceLock::new();
+
+/// The yt-dlp executable every lookup and stream runs
+pub(crate) fn yt_dlp() -> &'static str {
+    YT_DLP.get().copied().unwrap_or("yt-dlp")
+}
+
+/// Run `program` for everything that needs yt-dlp, songbird keeps borrowing it
+/// for as long as the bot runs
+pub(crate) fn set_yt_dlp(program: String) {
+    YT_DLP.get_or_init(|| Box::leak(program.into_boxed_str()));
+}
+
+/// One layer of the bot configuration, anything left out comes from the layer below
+#[derive(Deserialize, Debug, Default, Clone, Parser)]
+#[serde(deny_unknown_fields)]
+#[command(version, about = "A Discord music bot")]
+pub struct ConfigLayer {
+    /// Config file to read, yoitsus.toml by default
+    #[serde(skip)]
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Discord bot token
+    #[arg(long)]
+    pub token: Option<String>,
+    /// Application id, used to print an invite link
+    #[arg(long)]
+    pub client_id: Option<String>,
+    /// Prefix for text commands
+    #[arg(long)]
+    pub prefix: Option<String>,
+    /// Shown as what the bot is playing
+    #[arg(long)]
+    pub status: Option<String>,
+    /// More prefixes text commands answer to
+    #[arg(long, value_delimiter = ',')]
+    pub additional_prefixes: Option<Vec<String>>,
+    /// error, warn, info, debug, trace or off
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Where guild settings and saved queues are kept
+    #[arg(long)]
+    pub data_dir: Option<PathBuf>,
+    /// Folder of local music files
+    #[arg(long)]
+    pub music_dir: Option<PathBuf>,
+    /// yt-dlp executable to run
+    #[arg(long)]
+    pub yt_dlp: Option<String>,
+}
+
+impl ConfigLayer {
+    /// Values set in `other` win over the ones in `self`
+    fn merge(self, other: Self) -> Self {
+        Self {
+            config: other.config.or(self.config),
+            token: other.token.or(self.token),
+            client_id: other.client_id.or(self.client_id),
+            prefix: other.prefix.or(self.prefix),
+            status: other.status.or(self.status),
+            additional_prefixes: other.additional_prefixes.or(self.additional_prefixes),
+            log_level: other.log_level.or(self.log_level),
+            data_dir: other.data_dir.or(self.data_dir),
+            music_dir: other.music_dir.or(self.music_dir),
+            yt_dlp: other.yt_dlp.or(self.yt_dlp),
+        }
+    }
+
+    /// Read the config file at `path`. Only a missing default file is fine.
+    fn from_file(path: &Path, required: bool) -> anyhow::Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound && !required => {
+                return Ok(Self::default());
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Error reading config file {}", path.display()));
+            }
+        };
+        toml::from_str(&text).with_context(|| format!("Error in config file {}", path.display()))
+    }
+
+    /// The environment, also loaded from a `.env` file if there is one.
+    /// Empty variables count as unset, like the ones in `.envexample`.
+    fn from_env() -> anyhow::Result<Self> {
+        match dotenvy::dotenv() {
+            Ok(_) => {}
+            Err(err) if err.not_found() => {}
+            Err(err) => return Err(err).context("Error reading .env file"),
+        }
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.trim().is_empty());
+        Ok(Self {
+            config: var("YOITSUS_CONFIG").map(PathBuf::from),
+            token: var("DISCORD_TOKEN"),
+            client_id: var("DISCORD_CLIENT_ID"),
+            prefix: var("PREFIX"),
+            status: var("DISCORD_STATUS"),
+            additional_prefixes: var("ADDITIONAL_PREFIXES")
+                .map(|prefixes| prefixes.split(',').map(|p| p.trim().to_string()).collect()),
+            log_level: var("LOG_LEVEL"),
+            data_dir: var("DATA_DIR").map(PathBuf::from),
+            music_dir: var("MUSIC_DIR").map(PathBuf::from),
+            yt_dlp: var("YT_DLP"),
+        })
+    }
+}
+
+/// Checked bot configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub token: String,
+    pub client_id: Option<String>,
+    pub prefix: String,
+    pub status: String,
+    pub additional_prefixes: Vec<String>,
+    pub log_level: LevelFilter,
+    pub data_dir: PathBuf,
+    pub music_dir: Option<PathBuf>,
+    pub yt_dlp: String,
+}
+
+fn check_prefix(prefix: &str, what: &str) -> anyhow::Result<()> {
+    if prefix.is_empty() || prefix.contains(char::is_whitespace) {
+        return Err(anyhow!(
+            "The {} `{}` has to be at least one character without spaces",
+            what,
+            prefix
+        ));
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Read the config file, then the environment, then the command line flags,
+    /// later ones overriding earlier ones
+    pub fn load() -> anyhow::Result<Self> {
+        let flags = ConfigLayer::parse();
+        let env = ConfigLayer::from_env()?;
+        let config_file = flags.config.clone().or(env.config.clone());
+        let file = match &config_file {
+            Some(path) => ConfigLayer::from_file(path, true)?,
+            None => ConfigLayer::from_file(Path::new(DEFAULT_CONFIG_FILE), false)?,
+        };
+        let config_file = config_file.unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+        Self::check(file.merge(env).merge(flags), &config_file)
+    }
+
+    /// Check the merged layers, `config_file` is named in the errors
+    fn check(layer: ConfigLayer, config_file: &Path) -> anyhow::Result<Self> {
+        // The example config ships an empty token, that counts as not set
+        let token = layer
+            .token
+            .filter(|token| !token.trim().is_empty())
+            .ok_or(anyhow!(
+                "No Discord token, set `token` in {}, DISCORD_TOKEN or --token",
+                config_file.display()
+            ))?;
+        let prefix = layer.prefix.unwrap_or_else(|| "~".to_string());
+        check_prefix(&prefix, "prefix")?;
+        let additional_prefixes = layer
+            .additional_prefixes
+            .unwrap_or_else(|| vec!["^".to_string()]);
+        for additional in &additional_prefixes {
+            check_prefix(additional, "additional prefix")?;
+        }
+        let log_level = match layer.log_level {
+            Some(level) => level.trim().parse::<LevelFilter>().map_err(|_| {
+                anyhow!(
+                    "`{}` is not a log level, use error, warn, info, debug, trace or off",
+                    level
+                )
+            })?,
+            None => LevelFilter::INFO,
+        };
+        let yt_dlp = layer.yt_dlp.unwrap_or_else(|| "yt-dlp".to_string());
+        if yt_dlp.trim().is_empty() {
+            return Err(anyhow!("The yt-dlp path can't be empty"));
+        }
+        Ok(Self {
+            token,
+            client_id: layer.client_id.filter(|id| !id.is_empty()),
+            prefix,
+            status: layer.status.unwrap_or_else(|| "yo.help".to_string()),
+            additional_prefixes,
+            log_level,
+            data_dir: layer.data_dir.unwrap_or_else(|| PathBuf::from("data")),
+            music_dir: layer.music_dir.filter(|dir| !dir.as_os_str().is_empty()),
+            yt_dlp,
+        })
+    }
+}
@@ -1,4 +1,3 @@
-use poise;
 use poise::serenity_prelude as serenity;
 use reqwest::Client as HttpClient;
 use reqwest::ClientBuilder as HttpClientBuilder;
@@ -10,11 +9,10 @@ use serenity::model::prelude::OnlineStatus;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 use songbird::SerenityInit;
-use songbird::input::YoutubeDl;
-use std::env;
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, info, instrument};
+use tracing_subscriber::EnvFilter;
 struct HttpKey;
 
 impl TypeMapKey for HttpKey {
@@ -22,20 +20,45 @@ impl TypeMapKey for HttpKey {
 }
 
 mod commands;
+mod config;
+mod settings;
+mod shutdown;
+use commands::config::{check_permissions, config};
 use commands::help::help;
+use commands::music::attachments::{play_attachment, play_file};
+use commands::music::autoleave::{autoleave, on_voice_state_update, watch_idle};
+use commands::music::controls::{CONTROL_PREFIX, handle_control};
+use commands::music::direct::HttpResolver;
 use commands::music::funts::*;
+use commands::music::helpers::YoutubeResolver;
+use commands::music::history::{history, previous};
+use commands::music::library::{Library, LocalResolver, rescan};
+use commands::music::persist::{
+    QueueStore, RESUME_PREFIX, handle_resume, offer_resume, save_periodically, snapshot_all,
+};
 use commands::music::play;
+use commands::music::queue::{clear, move_song, queue, remove, skipto};
+use commands::music::radio::{radio, stations};
+use commands::music::session::Sessions;
+use commands::music::sources::ResolverChain;
+use commands::music::voteskip::voteskip;
+use config::{Config, set_yt_dlp};
+use settings::SettingsStore;
 
 // Types used by all command functions
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
 struct Data {
-    //cur_song:Arc<Mutex<Option<AuxMetadata>>>,
-    queue: Arc<Mutex<Vec<YoutubeDl<'static>>>>,
-    now_playing_msg: Arc<Mutex<Option<serenity::Message>>>,
+    sessions: Arc<Sessions>,
+    settings: Arc<SettingsStore>,
+    queues: Arc<QueueStore>,
+    library: Arc<Library>,
+    resolvers: Arc<ResolverChain>,
 }
 
-struct Handler;
+struct Handler {
+    status: String,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
@@ -44,9 +67,10 @@ impl EventHandler for Handler {
             "Connected as --> {} [id: {}]",
             ready.user.name, ready.user.id
         );
-        let status =
-            env::var("DISCORD_STATUS").expect("Set your DISCORD_STATUS environment variable!");
-        ctx.set_presence(Some(ActivityData::playing(&status)), OnlineStatus::Online);
+        ctx.set_presence(
+            Some(ActivityData::playing(&self.status)),
+            OnlineStatus::Online,
+        );
     }
 
     #[instrument(skip(self, _ctx))]
@@ -62,10 +86,10 @@ fn create_invite_link(client_id: &str, permissions: usize) -> String {
         .append_pair("client_id", client_id)
         .append_pair("permissions", &permissions.to_string())
         .append_pair("scope", "bot applications.commands");
-    return url.to_string();
+    url.to_string()
 }
 
-async fn on_error<'a>(error: poise::FrameworkError<'_, Data, Error>) {
+async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
     // This is our custom error handler
     // They are many errors that can occur, so we only handle the ones we want to customize
     // and forward the rest to the default handler
@@ -73,6 +97,23 @@ async fn on_error<'a>(error: poise::FrameworkError<'_, Data, Error>) {
         poise::FrameworkError::Setup { error, .. } => panic!("Failed to start bot: {:?}", error),
         poise::FrameworkError::Command { error, ctx, .. } => {
             println!("Error in command `{}`: {:?}", ctx.command().name, error,);
+            // Let the user know why nothing happened
+            if let Err(e) = ctx.say(error.to_string()).await {
+                println!("Error while reporting error: {}", e)
+            }
+        }
+        poise::FrameworkError::CommandCheckFailed {
+            error: Some(error),
+            ctx,
+            ..
+        } => {
+            // Say which rule stopped the command, blocked users get no answer at all
+            let reply = poise::CreateReply::default()
+                .content(error.to_string())
+                .ephemeral(true);
+            if let Err(e) = ctx.send(reply).await {
+                println!("Error while reporting failed check: {}", e)
+            }
         }
         error => {
             if let Err(e) = poise::builtins::on_error(error).await {
@@ -84,40 +125,95 @@ async fn on_error<'a>(error: poise::FrameworkError<'_, Data, Error>) {
 
 #[tokio::main]
 async fn main() {
-    dotenvy::dotenv().expect("Failed to load .env file.");
-
-    let token = env::var("DISCORD_TOKEN").expect("Set your DISCORD_TOKEN environment variable!");
-    let prefix = env::var("PREFIX").expect("Set your PREFIX environment variable!");
-    let client_id =
-        env::var("DISCORD_CLIENT_ID").expect("Set your CLIENT_ID environment variable!");
-    let invite_link = create_invite_link(&client_id, 36700160);
-    println!("Invite link: {}", invite_link);
-    // Initialise error tracing
-    tracing_subscriber::fmt::init();
+    let bot_config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    // Initialise error tracing, RUST_LOG still picks the level per module
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(bot_config.log_level.into())
+                .from_env_lossy(),
+        )
+        .init();
+    set_yt_dlp(bot_config.yt_dlp.clone());
+    let data_dir = bot_config.data_dir.clone();
+    let settings = Arc::new(
+        SettingsStore::load(data_dir.join("settings.json")).expect("Failed to load guild settings"),
+    );
+    let queues = Arc::new(
+        QueueStore::load(data_dir.join("queues.json")).expect("Failed to load saved queues"),
+    );
+    let library = Arc::new(Library::new(bot_config.music_dir.clone()));
+    // Asked in order, YouTube goes last as it takes anything that is not a link as a search
+    let resolvers = Arc::new(ResolverChain::new(vec![
+        Box::new(LocalResolver::new(library.clone())),
+        Box::new(HttpResolver),
+        Box::new(YoutubeResolver),
+    ]));
+    let sessions = Arc::new(Sessions::default());
+    let songbird = songbird::Songbird::serenity();
+    if let Some(client_id) = &bot_config.client_id {
+        let invite_link = create_invite_link(client_id, 36700160);
+        println!("Invite link: {}", invite_link);
+    }
 
     let options = poise::FrameworkOptions {
         commands: vec![
             help(),
             play(),
+            play_file(),
+            play_attachment(),
             next(),
             pause(),
             resume(),
             shuffle(),
+            stop(),
+            seek(),
+            volume(),
             disconnect(),
+            autoleave(),
+            voteskip(),
+            config(),
             loop_toggle(),
             join(),
             //nowplaying(),
             playlist(),
+            queue(),
+            remove(),
+            move_song(),
+            skipto(),
+            clear(),
+            previous(),
+            history(),
+            rescan(),
+            radio(),
+            stations(),
         ],
         prefix_options: poise::PrefixFrameworkOptions {
-            prefix: Some(prefix),
+            prefix: Some(bot_config.prefix.clone()),
+            // A prefix set with `config set prefix` works in that server next to the global one
+            dynamic_prefix: Some(|ctx| {
+                Box::pin(async move {
+                    Ok(match ctx.guild_id {
+                        Some(guild_id) => ctx.data.settings.get(guild_id).await.prefix,
+                        None => None,
+                    })
+                })
+            }),
             edit_tracker: Some(Arc::new(poise::EditTracker::for_timespan(
                 Duration::from_secs(3600),
             ))),
-            additional_prefixes: vec![
-                poise::Prefix::Literal("^"),
-                // poise::Prefix::Literal("hey bot"),
-            ],
+            // Poise wants them for the whole run, they are only read once at startup
+            additional_prefixes: bot_config
+                .additional_prefixes
+                .iter()
+                .map(|prefix| poise::Prefix::Literal(String::leak(prefix.clone())))
+                .collect(),
             ..Default::default()
         },
 
@@ -136,24 +232,39 @@ async fn main() {
             })
         },
         // Every command invocation must pass this check to continue execution
-        command_check: Some(|ctx| {
-            Box::pin(async move {
-                if ctx.author().id == 123456789 {
-                    return Ok(false);
-                }
-                Ok(true)
-            })
-        }),
+        command_check: Some(|ctx| Box::pin(check_permissions(ctx))),
 
         // Enforce command checks even for owners (enforced by default)
         // Set to true to bypass checks, which is useful for testing
         skip_checks_for_owners: false,
-        event_handler: |_ctx, event, _framework, _data| {
+        event_handler: |ctx, event, _framework, data| {
             Box::pin(async move {
                 println!(
                     "Got an event in event handler: {:?}",
                     event.snake_case_name()
                 );
+                match event {
+                    serenity::FullEvent::InteractionCreate {
+                        interaction: serenity::Interaction::Component(press),
+                    } if press.data.custom_id.starts_with(CONTROL_PREFIX) => {
+                        if let Err(e) = handle_control(ctx, data, press).await {
+                            println!("Error handling now playing control: {:?}", e);
+                        }
+                    }
+                    serenity::FullEvent::InteractionCreate {
+                        interaction: serenity::Interaction::Component(press),
+                    } if press.data.custom_id.starts_with(RESUME_PREFIX) => {
+                        if let Err(e) = handle_resume(ctx, data, press).await {
+                            println!("Error resuming playback: {:?}", e);
+                        }
+                    }
+                    serenity::FullEvent::VoiceStateUpdate { new, .. } => {
+                        if let Err(e) = on_voice_state_update(ctx, data, new).await {
+                            println!("Error handling voice state update: {:?}", e);
+                        }
+                    }
+                    _ => {}
+                }
                 Ok(())
             })
         },
@@ -161,16 +272,35 @@ async fn main() {
     };
 
     let framework = poise::Framework::builder()
-        .setup(move |ctx, _ready, framework| {
-            Box::pin(async move {
-                println!("Logged in as {}", _ready.user.name);
-                poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(Data {
-                    queue: Arc::new(Mutex::new(vec![])),
-                    // cur_song:Arc::new(Mutex::new(None)),
-                    now_playing_msg: Arc::new(Mutex::new(None)),
+        .setup({
+            let sessions = sessions.clone();
+            let queues = queues.clone();
+            move |ctx, _ready, framework| {
+                Box::pin(async move {
+                    println!("Logged in as {}", _ready.user.name);
+                    poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                    tokio::spawn(watch_idle(ctx.clone(), sessions.clone(), settings.clone()));
+                    offer_resume(&ctx.http, &queues).await;
+                    tokio::spawn(save_periodically(
+                        ctx.clone(),
+                        sessions.clone(),
+                        queues.clone(),
+                    ));
+                    let scan_library = library.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = scan_library.scan().await {
+                            info!("Local library not loaded: {:?}", err);
+                        }
+                    });
+                    Ok(Data {
+                        sessions,
+                        settings,
+                        queues,
+                        library,
+                        resolvers,
+                    })
                 })
-            })
+            }
         })
         .options(options)
         .build();
@@ -180,13 +310,30 @@ async fn main() {
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::GUILD_VOICE_STATES;
 
-    let mut client = serenity::ClientBuilder::new(token, intents)
+    let mut client = serenity::ClientBuilder::new(&bot_config.token, intents)
         .framework(framework)
-        .register_songbird()
-        .event_handler(Handler)
+        .register_songbird_with(songbird.clone())
+        .event_handler(Handler {
+            status: bot_config.status,
+        })
         .type_map_insert::<HttpKey>(HttpClientBuilder::new().use_rustls_tls().build().unwrap())
         .await
         .expect("Err creating client");
 
-    client.start().await.unwrap();
+    let shard_manager = client.shard_manager.clone();
+    let http = client.http.clone();
+    tokio::spawn(async move {
+        shutdown::wait_for_signal().await;
+        info!("Shutting down");
+        // Save before leaving, leaving empties the queues
+        if let Err(err) = queues.save(snapshot_all(&songbird, &sessions).await).await {
+            println!("Error saving queues: {:?}", err);
+        }
+        shutdown::leave_all(&songbird, &sessions, &http).await;
+        shard_manager.shutdown_all().await;
+    });
+
+    if let Err(why) = client.start().await {
+        println!("Client error: {:?}", why);
+    }
 }
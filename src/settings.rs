@@ -0,0 +1,178 @@
+use anyhow::Context;
+use poise::serenity_prelude::{ChannelId, GuildId, RoleId, UserId};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+/// Shortest wait before leaving an empty or idle voice channel, anything less
+/// drops the bot the moment a song ends
+pub const MIN_LEAVE_TIMEOUT_SECS: u64 = 10;
+
+/// Settings that only apply to a single guild, kept across restarts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// Prefix for text commands, the global prefix when not set
+    pub prefix: Option<String>,
+    /// Volume in percent applied to every song, 100 being unchanged
+    pub volume: u16,
+    /// Text channels music commands may be used in, any channel when empty
+    pub allowed_text_channels: BTreeSet<ChannelId>,
+    /// Voice channels the bot may join, any channel when empty
+    pub allowed_voice_channels: BTreeSet<ChannelId>,
+    /// Text channels music commands never work in, even when allowed
+    pub denied_text_channels: BTreeSet<ChannelId>,
+    /// Voice channels the bot never joins, even when allowed
+    pub denied_voice_channels: BTreeSet<ChannelId>,
+    /// Most songs waiting in the queue at once
+    pub max_queue: usize,
+    /// Longest song in seconds that may be queued, no limit when 0
+    pub max_track_secs: u64,
+    /// Channel the now playing message goes to, the channel of the last command when not set
+    pub announce_channel: Option<ChannelId>,
+    /// Stay in the voice channel even when alone or idle (24/7 mode)
+    pub stay_connected: bool,
+    /// Seconds to wait before leaving once everyone else left the voice channel
+    pub alone_timeout_secs: u64,
+    /// Seconds without anything playing before leaving the voice channel
+    pub idle_timeout_secs: u64,
+    /// Number of latest uploads queued for a channel link
+    pub channel_uploads: usize,
+    /// Most songs a single `play` may add to the queue
+    pub max_enqueue: usize,
+    /// Radio stream links saved under a short name, for `radio <name>`
+    pub radio_stations: BTreeMap<String, String>,
+    /// Skipping takes votes from the listeners instead of a single `next`
+    pub vote_skip: bool,
+    /// Share of the listeners in percent that has to vote before a song is skipped
+    pub skip_vote_percent: u8,
+    /// Members with this role skip without a vote and may use the DJ commands
+    pub dj_role: Option<RoleId>,
+    /// Commands only DJs and admins may use, none until restricted with `config restrict`
+    pub dj_commands: BTreeSet<String>,
+    /// Anyone alone with the bot in its voice channel may use the DJ commands
+    pub alone_bypass: bool,
+    /// Users whose commands are ignored
+    pub blocked_users: BTreeSet<UserId>,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            prefix: None,
+            volume: 100,
+            allowed_text_channels: BTreeSet::new(),
+            allowed_voice_channels: BTreeSet::new(),
+            denied_text_channels: BTreeSet::new(),
+            denied_voice_channels: BTreeSet::new(),
+            max_queue: 500,
+            max_track_secs: 0,
+            announce_channel: None,
+            stay_connected: false,
+            alone_timeout_secs: 60,
+            idle_timeout_secs: 300,
+            channel_uploads: 10,
+            max_enqueue: 100,
+            radio_stations: BTreeMap::new(),
+            vote_skip: false,
+            skip_vote_percent: 50,
+            dj_role: None,
+            dj_commands: BTreeSet::new(),
+            alone_bypass: true,
+            blocked_users: BTreeSet::new(),
+        }
+    }
+}
+
+impl GuildSettings {
+    /// Whether music commands may be used in the text channel `channel_id`
+    pub fn allows_text_channel(&self, channel_id: ChannelId) -> bool {
+        channel_allowed(
+            &self.allowed_text_channels,
+            &self.denied_text_channels,
+            channel_id,
+        )
+    }
+
+    /// Whether the bot may join the voice channel `channel_id`
+    pub fn allows_voice_channel(&self, channel_id: ChannelId) -> bool {
+        channel_allowed(
+            &self.allowed_voice_channels,
+            &self.denied_voice_channels,
+            channel_id,
+        )
+    }
+}
+
+/// A denied channel stays denied even when it is on the allow list as well
+fn channel_allowed(
+    allowed: &BTreeSet<ChannelId>,
+    denied: &BTreeSet<ChannelId>,
+    channel_id: ChannelId,
+) -> bool {
+    !denied.contains(&channel_id) && (allowed.is_empty() || allowed.contains(&channel_id))
+}
+
+/// All guild settings, saved as one json file in the data directory
+pub struct SettingsStore {
+    path: PathBuf,
+    guilds: Mutex<HashMap<GuildId, GuildSettings>>,
+}
+
+impl SettingsStore {
+    /// Load the settings saved at `path`, starting empty if nothing was saved yet
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let guilds = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Error parsing settings file {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Error reading settings file {}", path.display()));
+            }
+        };
+        Ok(Self {
+            path,
+            guilds: Mutex::new(guilds),
+        })
+    }
+
+    pub async fn get(&self, guild_id: GuildId) -> GuildSettings {
+        self.guilds
+            .lock()
+            .await
+            .get(&guild_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Change the settings of a guild and write them to disk, returning the new settings
+    pub async fn update(
+        &self,
+        guild_id: GuildId,
+        change: impl FnOnce(&mut GuildSettings),
+    ) -> anyhow::Result<GuildSettings> {
+        let mut guilds = self.guilds.lock().await;
+        let settings = guilds.entry(guild_id).or_default();
+        change(settings);
+        let settings = settings.clone();
+        write_json(&self.path, &*guilds).await?;
+        Ok(settings)
+    }
+}
+
+/// Write `value` as json to `path`, creating the data directory if needed
+pub(crate) async fn write_json(path: &Path, value: &impl Serialize) -> anyhow::Result<()> {
+    let json = serde_json::to_vec_pretty(value)?;
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    // Write next to the real file first so a crash never leaves half a file behind
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, json)
+        .await
+        .with_context(|| format!("Error writing {}", tmp_path.display()))?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
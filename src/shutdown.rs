@@ -0,0 +1,43 @@
+use crate::commands::music::funts::leave_voice;
+use crate::commands::music::session::Sessions;
+use poise::serenity_prelude as serenity;
+use songbird::Songbird;
+use tracing::{info, warn};
+
+/// Footer left on the now playing messages when the bot goes down
+const RESTART_NOTE: &str = "Bot restarting, playback stopped";
+
+/// Wait for Ctrl+C, or the SIGTERM sent by `docker stop`
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for Ctrl+C");
+}
+
+/// Leave every voice channel the bot is in, so no ghost connections or stale
+/// now playing messages are left behind
+pub async fn leave_all(manager: &Songbird, sessions: &Sessions, http: &serenity::Http) {
+    for (guild_id, session) in sessions.all().await {
+        if manager.get(guild_id).is_none() {
+            continue;
+        }
+        info!("Leaving voice channel in guild {} for shutdown", guild_id);
+        if let Err(err) = leave_voice(manager, &session, guild_id, http, Some(RESTART_NOTE)).await {
+            warn!(
+                "Error leaving voice channel in guild {}: {:?}",
+                guild_id, err
+            );
+        }
+    }
+}